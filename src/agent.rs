@@ -1,132 +1,744 @@
-use macroquad::{prelude::*, rand::ChooseRandom};
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use quad_rand::ChooseRandom;
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::external::Session;
 use crate::{
-    board::{Board, GameState},
+    board::{Action, Board, GameState},
+    external::ExternalEngine,
+    mcts::{MctsLimit, Tree},
+    solver::{self, Solution, Solver},
+    transposition::{Bound, Entry, TranspositionTable},
     Turn,
 };
 
-#[derive(PartialEq, Eq, Debug)]
+/// Multiplied by the player's number and mixed into the Zobrist hash, so
+/// positions searched for each player and with each player to move are stored
+/// separately.
+const SEARCHER_KEY: u64 = 0x5851_F42D_4C95_7F2D;
+const TO_MOVE_KEY: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// The transposition table key for a search node choosing a move for `turn`
+/// with `to_move` to play. The board's dimensions, players and rules are
+/// included so a table can be shared between different games.
+pub(crate) fn search_key(board: &Board, turn: &Turn, to_move: &Turn) -> u64 {
+    let mut key = board.hash();
+    key ^= (board.rows() as u64)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .rotate_left(17)
+        ^ (board.cols() as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9)
+        ^ (board.x_to_win() as u64).wrapping_mul(0x94D0_49BB_1331_11EB)
+        ^ (board.players() as u64).rotate_left(40)
+        ^ (board.rules().popout as u64).rotate_left(50)
+        ^ (board.rules().gravity as u64).rotate_left(51)
+        ^ (board.rules().wrap_horizontal as u64).rotate_left(52)
+        ^ (board.rules().wrap_vertical as u64).rotate_left(53);
+    key ^= (turn.index() as u64 + 1).wrapping_mul(SEARCHER_KEY);
+    key ^= (to_move.index() as u64 + 1)
+        .wrapping_mul(TO_MOVE_KEY)
+        .rotate_left(23);
+    key
+}
+
+/// Returns the current time in seconds. Only differences between readings are
+/// used, so any fixed starting point works.
+pub type Clock = fn() -> f64;
+
+/// A [`Clock`] based on the system time, for front ends without their own.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn system_clock() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// How long a searching agent may think about each move
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SearchLimits {
+    /// Wall clock budget in seconds
+    pub think_time: f64,
+    /// Deepest iteration to search, regardless of time left
+    pub max_depth: u32,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            think_time: 1.0,
+            max_depth: 64,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum Agent {
     Player,
     Random,
-    AlphaBeta,
+    AlphaBeta(SearchLimits),
+    Mcts(MctsLimit),
+    /// Plays perfectly once the position can be solved within the first half
+    /// of its time budget, and like [`Agent::AlphaBeta`] until then. Only two
+    /// player games can be solved, so with more it is [`Agent::AlphaBeta`]
+    Solver(SearchLimits),
+    /// Another program playing through the [`protocol`](crate::protocol)
+    External(ExternalEngine),
+    /// Someone playing from another instance over the network
+    Remote,
+}
+
+impl Agent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Agent::Player => "Player",
+            Agent::Random => "Random",
+            Agent::AlphaBeta(_) => "AlphaBeta",
+            Agent::Mcts(_) => "MCTS",
+            Agent::Solver(_) => "Solver",
+            Agent::External(_) => "External",
+            Agent::Remote => "Remote",
+        }
+    }
 }
 
+/// Picks a move for a computer controlled agent, blocking until it is found.
+///
+/// Returns `None` for [`Agent::Player`] and [`Agent::Remote`], whose moves come
+/// from the front end.
 pub fn compute_turn(
-    current_turn: &mut Turn,
     agent: &Agent,
-    board: &mut Board,
-    piece_size: f32,
-) -> Option<usize> {
-    let chosen_move = match agent {
-        Agent::Player => player_turn(board, current_turn, piece_size),
-        Agent::Random => random_turn(board),
-        Agent::AlphaBeta => alpha_beta_turn(board, current_turn),
-    };
-    if let Some(col) = chosen_move {
-        if board.place(col, current_turn) {
-            *current_turn = current_turn.next();
-        }
-        return chosen_move;
-    };
-    None
+    board: &Board,
+    turn: &Turn,
+    table: &mut TranspositionTable,
+    clock: Clock,
+) -> Option<Action> {
+    TurnSearch::new(agent, board, turn, clock)
+        .step(table, f64::INFINITY, &AtomicBool::new(false))
+        .flatten()
 }
 
-pub fn player_turn(board: &Board, turn: &Turn, piece_size: f32) -> Option<usize> {
-    let mouse_psn = mouse_position();
-
-    let col = board.mouse_hover(piece_size, mouse_psn, turn);
+pub fn random_turn(board: &Board, turn: &Turn) -> Option<Action> {
+    let moves = board.moves(turn);
 
-    if col.is_some() && is_mouse_button_pressed(MouseButton::Left) {
-        col
+    if !moves.is_empty() {
+        moves.choose().cloned()
     } else {
         None
     }
 }
 
-pub fn random_turn(board: &Board) -> Option<usize> {
-    let moves = board.moves();
+/// An agent working out its next move.
+///
+/// The work is done in slices through [`TurnSearch::step`], so a front end
+/// can run it on another thread or a little at a time between frames.
+pub struct TurnSearch {
+    agent: Agent,
+    board: Board,
+    turn: Turn,
+    clock: Clock,
+    deadline: f64,
+    /// The next alpha-beta iteration to run
+    depth: u32,
+    /// Best move of the last iteration that finished
+    best: Option<Action>,
+    /// The search tree for [`Agent::Mcts`]
+    tree: Option<Tree>,
+    /// Set while [`Agent::Solver`] is still trying to solve the position
+    solving: bool,
+    /// When [`Agent::Solver`] gives up on solving and falls back to alpha-beta
+    solve_deadline: f64,
+    /// The value of the position, once [`Agent::Solver`] has solved it
+    solution: Option<Solution>,
+    /// Why [`Agent::External`] couldn't give a move and a random one was
+    /// played instead
+    error: Option<String>,
+    /// The running program for [`Agent::External`]
+    #[cfg(not(target_arch = "wasm32"))]
+    session: Option<Session>,
+}
 
-    if !moves.is_empty() {
-        moves.choose().cloned()
-    } else {
+impl TurnSearch {
+    pub fn new(agent: &Agent, board: &Board, turn: &Turn, clock: Clock) -> Self {
+        let think_time = match agent {
+            Agent::AlphaBeta(limits) | Agent::Solver(limits) => limits.think_time,
+            Agent::Mcts(MctsLimit::Time(think_time)) => *think_time,
+            Agent::Mcts(MctsLimit::Iterations(_)) => f64::INFINITY,
+            Agent::External(engine) => engine.think_time,
+            _ => 0.0,
+        };
+        let tree = match agent {
+            Agent::Mcts(_) => Some(Tree::new(board, turn)),
+            _ => None,
+        };
+
+        let solving = matches!(agent, Agent::Solver(_)) && solver::can_solve(board);
+        let start = clock();
+
+        TurnSearch {
+            agent: agent.clone(),
+            board: board.clone(),
+            turn: *turn,
+            clock,
+            deadline: start + think_time,
+            depth: 1,
+            best: None,
+            tree,
+            solving,
+            solve_deadline: start + think_time / 2.0,
+            solution: None,
+            error: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            session: None,
+        }
+    }
+
+    /// The exact value of the position for the player to move, if the agent
+    /// managed to solve it.
+    pub fn solution(&self) -> Option<Solution> {
+        self.solution
+    }
+
+    /// What went wrong if the agent couldn't work out a move of its own and
+    /// played a random one instead.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// A note about how the agent came to its move for the front end to show,
+    /// such as the value of a solved position or why an engine failed.
+    pub fn status(&self) -> Option<String> {
+        match (&self.error, self.solution) {
+            (Some(error), _) => Some(error.clone()),
+            (None, Some(solution)) => Some(format!("Solved: {}", solution.value)),
+            (None, None) => None,
+        }
+    }
+
+    /// Works on the move until the clock reaches `until` or `cancel` is set.
+    ///
+    /// Returns `None` while there is more work to do and `Some` with the chosen
+    /// move once the agent has decided. A cancelled search returns whatever
+    /// it has found so far.
+    pub fn step(
+        &mut self,
+        table: &mut TranspositionTable,
+        until: f64,
+        cancel: &AtomicBool,
+    ) -> Option<Option<Action>> {
+        match &self.agent {
+            Agent::Player | Agent::Remote => Some(None),
+            Agent::Random => Some(random_turn(&self.board, &self.turn)),
+            Agent::AlphaBeta(limits) => {
+                let limits = *limits;
+                self.alpha_beta_step(table, &limits, until, cancel)
+            }
+            Agent::Mcts(limit) => {
+                let limit = *limit;
+                self.mcts_step(limit, until, cancel)
+            }
+            Agent::Solver(limits) => {
+                let limits = *limits;
+                if self.solving {
+                    if let Some(chosen) = self.solve_step(table, until, cancel) {
+                        return Some(chosen);
+                    }
+                    if self.solving {
+                        return None;
+                    }
+                }
+                self.alpha_beta_step(table, &limits, until, cancel)
+            }
+            Agent::External(engine) => {
+                let engine = engine.clone();
+                self.external_step(&engine, until, cancel)
+            }
+        }
+    }
+
+    /// Waits on the engine program for its move. If it can't be run or doesn't
+    /// give a legal move in time a random move is played, so a broken engine
+    /// can't stall the game, and the reason is kept in `error`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn external_step(
+        &mut self,
+        engine: &ExternalEngine,
+        until: f64,
+        cancel: &AtomicBool,
+    ) -> Option<Option<Action>> {
+        let result = match &mut self.session {
+            Some(session) => session.poll(self.clock, until, cancel)?,
+            None => match Session::start(engine, &self.board, &self.turn, self.clock) {
+                Ok(session) => self
+                    .session
+                    .insert(session)
+                    .poll(self.clock, until, cancel)?,
+                Err(err) => Err(err.to_string()),
+            },
+        };
+        self.session = None;
+
+        match result {
+            Ok(action) => Some(Some(action)),
+            Err(err) => {
+                self.error = Some(format!(
+                    "External engine '{}': {err}, played a random move instead",
+                    engine.command
+                ));
+                Some(random_turn(&self.board, &self.turn))
+            }
+        }
+    }
+
+    /// Programs can't be started from the web, so a random move is played.
+    #[cfg(target_arch = "wasm32")]
+    fn external_step(
+        &mut self,
+        engine: &ExternalEngine,
+        _until: f64,
+        _cancel: &AtomicBool,
+    ) -> Option<Option<Action>> {
+        self.error = Some(format!(
+            "External engine '{}': programs can't be run on the web, played a random move instead",
+            engine.command
+        ));
+        Some(random_turn(&self.board, &self.turn))
+    }
+
+    /// Tries to solve the position outright. Returns the perfect move if that
+    /// worked, otherwise `None`, clearing `solving` once the solver has used
+    /// up its share of the time.
+    fn solve_step(
+        &mut self,
+        table: &mut TranspositionTable,
+        until: f64,
+        cancel: &AtomicBool,
+    ) -> Option<Option<Action>> {
+        let stopper = Stopper::new(self.clock, until.min(self.solve_deadline), cancel);
+        let mut solver = Solver::new(table, stopper, &self.board);
+        if let Some(solution) = solver.solve(&mut self.board, &self.turn) {
+            self.solution = Some(solution);
+            return Some(solution.best_move);
+        }
+
+        if (self.clock)() >= self.solve_deadline || cancel.load(Ordering::Relaxed) {
+            self.solving = false;
+        }
         None
     }
+
+    /// Runs playouts until the agent's limit is reached, then plays the most
+    /// visited move.
+    fn mcts_step(
+        &mut self,
+        limit: MctsLimit,
+        until: f64,
+        cancel: &AtomicBool,
+    ) -> Option<Option<Action>> {
+        let tree = self.tree.as_mut().unwrap();
+
+        loop {
+            let done = match limit {
+                MctsLimit::Time(_) => (self.clock)() >= self.deadline,
+                MctsLimit::Iterations(iterations) => tree.iterations() >= iterations,
+            };
+            if done || cancel.load(Ordering::Relaxed) {
+                return Some(tree.best_move());
+            }
+            if (self.clock)() >= until {
+                return None;
+            }
+
+            tree.iterate();
+        }
+    }
+
+    /// Searches one ply deeper each iteration until the time budget runs out,
+    /// then plays the best move of the last iteration that finished. If not even
+    /// the first iteration completes a random move is played instead.
+    ///
+    /// An iteration cut short by the end of a slice is restarted on the next
+    /// step. Every subtree it finished is already in the table, so the work is
+    /// not lost.
+    fn alpha_beta_step(
+        &mut self,
+        table: &mut TranspositionTable,
+        limits: &SearchLimits,
+        until: f64,
+        cancel: &AtomicBool,
+    ) -> Option<Option<Action>> {
+        let cells = (self.board.rows() * self.board.cols()) as u32;
+        let mut search = Search {
+            turn: self.turn,
+            table,
+            stopper: Stopper::new(self.clock, until.min(self.deadline), cancel),
+        };
+
+        while self.depth <= limits.max_depth.min(cells) {
+            let (action, value) =
+                search.minimax(&mut self.board, self.depth, i32::MIN, i32::MAX, self.turn);
+            if search.stopper.aborted {
+                let out_of_time = (self.clock)() >= self.deadline;
+                if !out_of_time && !cancel.load(Ordering::Relaxed) {
+                    return None;
+                }
+                break;
+            }
+            self.best = action;
+            self.depth += 1;
+
+            // A forced result won't change with a deeper search
+            if value == i32::MAX || value == i32::MIN || (self.clock)() >= self.deadline {
+                break;
+            }
+            if (self.clock)() >= until {
+                return None;
+            }
+        }
+
+        Some(self.best.or_else(|| random_turn(&self.board, &self.turn)))
+    }
 }
 
-pub fn alpha_beta_turn(board: &Board, turn: &Turn) -> Option<usize> {
-    let (col, _) = minimax(board.clone(), 5, i32::MIN, i32::MAX, true, turn);
-    col
+/// How many nodes to visit between checks of the clock
+const NODES_PER_CLOCK_CHECK: u64 = 1024;
+
+/// Decides when a tree search has to stop, either because the clock has
+/// reached `stop_at` or because the search was cancelled.
+pub(crate) struct Stopper<'a> {
+    clock: Clock,
+    stop_at: f64,
+    cancel: &'a AtomicBool,
+    nodes: u64,
+    /// Set once the search has to stop. Results from an aborted search are
+    /// meaningless and are never stored in the table.
+    pub aborted: bool,
 }
 
-fn minimax(
-    board: Board,
-    depth: u32,
-    mut alpha: i32,
-    mut beta: i32,
-    maximizing_player: bool,
-    turn: &Turn,
-) -> (Option<usize>, i32) {
-    if depth == 0 {
-        return (None, board.score_position(turn));
+impl<'a> Stopper<'a> {
+    pub fn new(clock: Clock, stop_at: f64, cancel: &'a AtomicBool) -> Self {
+        Stopper {
+            clock,
+            stop_at,
+            cancel,
+            nodes: 0,
+            aborted: false,
+        }
     }
 
-    let board_state = board.game_state();
+    /// Counts a visited node, returning true if the search has to stop.
+    pub fn tick(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(NODES_PER_CLOCK_CHECK)
+            && ((self.clock)() >= self.stop_at || self.cancel.load(Ordering::Relaxed))
+        {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+}
 
-    if board_state != GameState::OnGoing {
-        if board_state == GameState::P1Win {
-            return match turn {
-                Turn::Player1 => (None, i32::MAX),
-                Turn::Player2 => (None, i32::MIN),
-            };
-        } else if board_state == GameState::P2Win {
-            return match turn {
-                Turn::Player1 => (None, i32::MIN),
-                Turn::Player2 => (None, i32::MAX),
-            };
-        } else {
+/// The state shared by every node of one alpha-beta search
+struct Search<'a> {
+    /// The player the search is choosing a move for
+    turn: Turn,
+    table: &'a mut TranspositionTable,
+    stopper: Stopper<'a>,
+}
+
+impl Search<'_> {
+    /// Searches by making and taking back moves on a single board. A move that
+    /// decides the game ends the search immediately, scored by who won.
+    ///
+    /// With more than two players the search is paranoid: every other player
+    /// is assumed to be working together against the one the search is for,
+    /// which keeps it a two sided search that alpha-beta can prune.
+    fn minimax(
+        &mut self,
+        board: &mut Board,
+        depth: u32,
+        mut alpha: i32,
+        mut beta: i32,
+        to_move: Turn,
+    ) -> (Option<Action>, i32) {
+        if self.stopper.tick() {
             return (None, 0);
         }
-    }
 
-    let valid_locations = board.moves();
+        let turn = self.turn;
 
-    if valid_locations.is_empty() {
-        // We should never get here but its just in case
-        return (None, 0);
-    }
+        if depth == 0 {
+            return (None, board.score_position(&turn));
+        }
+
+        let mut valid_locations = board.moves(&to_move);
 
-    if maximizing_player {
-        let mut value = i32::MIN;
-        let mut column = *valid_locations.choose().unwrap();
-        for col in valid_locations {
-            let b_copy = board.result(col, turn);
-            let (_, new_score) = minimax(b_copy, depth - 1, alpha, beta, false, turn);
-            if new_score > value {
-                value = new_score;
-                column = col;
+        if valid_locations.is_empty() {
+            // There is nothing to do, so the game is a tie
+            return (None, 0);
+        }
+
+        let key = search_key(board, &turn, &to_move);
+        let next = to_move.next(board.players());
+
+        let (alpha_orig, beta_orig) = (alpha, beta);
+        if let Some(entry) = self.table.get(key) {
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return (entry.best_move, entry.value),
+                    Bound::Lower => alpha = alpha.max(entry.value),
+                    Bound::Upper => beta = beta.min(entry.value),
+                }
+                if alpha >= beta {
+                    return (entry.best_move, entry.value);
+                }
             }
-            alpha = alpha.max(value);
-            if alpha >= beta {
-                break;
+
+            // Search the best move from last time first for more cutoffs
+            if let Some(pos) = valid_locations
+                .iter()
+                .position(|&a| Some(a) == entry.best_move)
+            {
+                valid_locations[..=pos].rotate_right(1);
             }
         }
-        (Some(column), value)
-    } else {
-        let mut value = i32::MAX;
-        let mut column = *valid_locations.choose().unwrap();
-        for col in valid_locations {
-            let b_copy = board.result(col, &turn.next());
-            let (_, new_score) = minimax(b_copy, depth - 1, alpha, beta, true, turn);
-            if new_score < value {
-                value = new_score;
-                column = col;
+
+        let (best, value) = if to_move == turn {
+            let mut value = i32::MIN;
+            let mut best = *valid_locations.choose().unwrap();
+            for action in valid_locations {
+                board.play(action, &turn);
+                let new_score = self.score_move(board, depth, alpha, beta, next);
+                board.take_back(action, &turn);
+                if self.stopper.aborted {
+                    return (None, 0);
+                }
+                if new_score > value {
+                    value = new_score;
+                    best = action;
+                }
+                alpha = alpha.max(value);
+                if alpha >= beta {
+                    break;
+                }
             }
-            beta = beta.min(value);
-            if alpha >= beta {
-                break;
+            (best, value)
+        } else {
+            let mut value = i32::MAX;
+            let mut best = *valid_locations.choose().unwrap();
+            for action in valid_locations {
+                board.play(action, &to_move);
+                let new_score = self.score_move(board, depth, alpha, beta, next);
+                board.take_back(action, &to_move);
+                if self.stopper.aborted {
+                    return (None, 0);
+                }
+                if new_score < value {
+                    value = new_score;
+                    best = action;
+                }
+                beta = beta.min(value);
+                if alpha >= beta {
+                    break;
+                }
+            }
+            (best, value)
+        };
+
+        let bound = if value <= alpha_orig {
+            Bound::Upper
+        } else if value >= beta_orig {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.store(Entry {
+            key,
+            depth,
+            value,
+            bound,
+            best_move: Some(best),
+        });
+
+        (Some(best), value)
+    }
+
+    /// Scores the position a move at `depth` led to, searching on with `next`
+    /// to move unless the game is over. A pop can win for someone other than
+    /// the player who made it, so the winner decides the score.
+    fn score_move(
+        &mut self,
+        board: &mut Board,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+        next: Turn,
+    ) -> i32 {
+        match board.game_state() {
+            GameState::Win(winner) if winner == self.turn => i32::MAX,
+            GameState::Win(_) => i32::MIN,
+            GameState::Tie => 0,
+            GameState::OnGoing => self.minimax(board, depth - 1, alpha, beta, next).1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::board::Rules;
+
+    /// A clock that never moves, so only the depth limit ends a search
+    fn frozen_clock() -> f64 {
+        0.0
+    }
+
+    thread_local! {
+        static TICKS: Cell<f64> = const { Cell::new(0.0) };
+    }
+
+    /// A clock that moves on a second every time it is read
+    fn ticking_clock() -> f64 {
+        TICKS.with(|ticks| {
+            ticks.set(ticks.get() + 1.0);
+            ticks.get()
+        })
+    }
+
+    const EMPTY: &str = "6x7x4 7/7/7/7/7/7 r";
+
+    fn alpha_beta(think_time: f64, max_depth: u32) -> Agent {
+        Agent::AlphaBeta(SearchLimits {
+            think_time,
+            max_depth,
+        })
+    }
+
+    #[test]
+    fn alpha_beta_takes_a_win_in_one() {
+        let (board, turn) = Board::from_notation("6x7x4 7/7/7/7/yyy4/rrr4 r").unwrap();
+        let mut table = TranspositionTable::new(1);
+        let action = compute_turn(&alpha_beta(1.0, 8), &board, &turn, &mut table, frozen_clock);
+        assert_eq!(action, Some(Action::Drop(3)));
+    }
+
+    #[test]
+    fn alpha_beta_blocks_a_win_in_one() {
+        let (board, turn) = Board::from_notation("6x7x4 7/7/7/7/y6/yrrr3 y").unwrap();
+        let mut table = TranspositionTable::new(1);
+        let action = compute_turn(&alpha_beta(1.0, 4), &board, &turn, &mut table, frozen_clock);
+        assert_eq!(action, Some(Action::Drop(4)));
+    }
+
+    #[test]
+    fn iterations_stop_at_the_depth_limit() {
+        let (board, _) = Board::from_notation(EMPTY).unwrap();
+        let mut table = TranspositionTable::new(1);
+        let mut search = TurnSearch::new(&alpha_beta(1.0, 3), &board, &Turn::Player1, frozen_clock);
+        let action = search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert!(matches!(action, Some(Some(Action::Drop(_)))));
+        assert_eq!(search.depth, 4);
+    }
+
+    #[test]
+    fn a_spent_budget_still_finishes_the_first_iteration() {
+        let (board, _) = Board::from_notation(EMPTY).unwrap();
+        let mut table = TranspositionTable::new(1);
+        let mut search =
+            TurnSearch::new(&alpha_beta(0.0, 64), &board, &Turn::Player1, frozen_clock);
+        let action = search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert_eq!(action, Some(search.best));
+        assert!(search.best.is_some());
+        assert_eq!(search.depth, 2);
+    }
+
+    #[test]
+    fn running_out_of_time_plays_the_last_finished_iteration() {
+        let (board, _) = Board::from_notation("8x9x4 9/9/9/9/9/9/9/9 r").unwrap();
+        let mut table = TranspositionTable::new(1);
+        let mut search =
+            TurnSearch::new(&alpha_beta(20.0, 64), &board, &Turn::Player1, ticking_clock);
+        let action = search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert!(search.depth > 1 && search.depth <= 64);
+        assert_eq!(action, Some(search.best));
+        assert!(ticking_clock() >= search.deadline);
+    }
+
+    #[test]
+    fn slices_carry_on_from_the_last_iteration() {
+        let (board, _) = Board::from_notation(EMPTY).unwrap();
+        let mut table = TranspositionTable::new(1);
+        let mut search = TurnSearch::new(&alpha_beta(1.0, 2), &board, &Turn::Player1, frozen_clock);
+        let cancel = AtomicBool::new(false);
+
+        // Every slice is already over, so each one runs a single iteration
+        assert_eq!(search.step(&mut table, 0.0, &cancel), None);
+        assert_eq!(search.depth, 2);
+        assert_eq!(search.step(&mut table, 0.0, &cancel), None);
+        assert_eq!(search.depth, 3);
+
+        // Past the depth limit the move is played straight away
+        let action = search.step(&mut table, 0.0, &cancel);
+        assert!(matches!(action, Some(Some(Action::Drop(_)))));
+        assert_eq!(search.depth, 3);
+    }
+
+    #[test]
+    fn search_keys_depend_on_who_is_searching_and_moving() {
+        let (board, _) = Board::from_notation(EMPTY).unwrap();
+        let (one, two) = (Turn::Player1, Turn::Player2);
+        let keys = [
+            search_key(&board, &one, &one),
+            search_key(&board, &one, &two),
+            search_key(&board, &two, &one),
+            search_key(&board, &two, &two),
+        ];
+        for i in 0..keys.len() {
+            for j in i + 1..keys.len() {
+                assert_ne!(keys[i], keys[j]);
             }
         }
-        (Some(column), value)
+
+        let mut popout = board.clone();
+        popout.set_rules(Rules {
+            popout: true,
+            ..Rules::default()
+        });
+        assert_ne!(search_key(&popout, &one, &one), keys[0]);
+    }
+
+    #[test]
+    fn a_broken_engine_is_reported_and_a_random_move_played() {
+        let (board, turn) = Board::from_notation(EMPTY).unwrap();
+        let agent = Agent::External(ExternalEngine {
+            command: "./no-such-engine".to_owned(),
+            ..ExternalEngine::default()
+        });
+        let mut table = TranspositionTable::new(1);
+        let mut search = TurnSearch::new(&agent, &board, &turn, system_clock);
+        let action = search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert!(matches!(action, Some(Some(Action::Drop(_)))));
+
+        let error = search.error().unwrap();
+        assert!(
+            error.starts_with("External engine './no-such-engine': "),
+            "{error}"
+        );
+        assert_eq!(search.status().as_deref(), Some(error));
+    }
+
+    #[test]
+    fn working_agents_have_nothing_to_report() {
+        let (board, turn) = Board::from_notation(EMPTY).unwrap();
+        let mut table = TranspositionTable::new(1);
+        let mut search = TurnSearch::new(&alpha_beta(1.0, 2), &board, &turn, frozen_clock);
+        search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert_eq!(search.error(), None);
+        assert_eq!(search.status(), None);
     }
 }
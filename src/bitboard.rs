@@ -0,0 +1,186 @@
+/// A fixed length set of bits used to store one layer of a [`Board`](crate::board::Board).
+///
+/// Boards that fit in a single machine word are kept inline so that cloning and
+/// updating them never allocates; anything larger falls back to a list of words.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BitSet {
+    Small(u64),
+    Medium(u128),
+    Large(Box<[u64]>),
+}
+
+impl BitSet {
+    /// Creates an empty set able to hold `len` bits.
+    pub fn new(len: usize) -> Self {
+        if len <= 64 {
+            BitSet::Small(0)
+        } else if len <= 128 {
+            BitSet::Medium(0)
+        } else {
+            BitSet::Large(vec![0; len.div_ceil(64)].into_boxed_slice())
+        }
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        match self {
+            BitSet::Small(bits) => bits >> i & 1 == 1,
+            BitSet::Medium(bits) => bits >> i & 1 == 1,
+            BitSet::Large(words) => words[i / 64] >> (i % 64) & 1 == 1,
+        }
+    }
+
+    pub fn set(&mut self, i: usize) {
+        match self {
+            BitSet::Small(bits) => *bits |= 1 << i,
+            BitSet::Medium(bits) => *bits |= 1 << i,
+            BitSet::Large(words) => words[i / 64] |= 1 << (i % 64),
+        }
+    }
+
+    pub fn unset(&mut self, i: usize) {
+        match self {
+            BitSet::Small(bits) => *bits &= !(1 << i),
+            BitSet::Medium(bits) => *bits &= !(1 << i),
+            BitSet::Large(words) => words[i / 64] &= !(1 << (i % 64)),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            BitSet::Small(bits) => *bits = 0,
+            BitSet::Medium(bits) => *bits = 0,
+            BitSet::Large(words) => words.fill(0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            BitSet::Small(bits) => *bits == 0,
+            BitSet::Medium(bits) => *bits == 0,
+            BitSet::Large(words) => words.iter().all(|&w| w == 0),
+        }
+    }
+
+    /// Keeps only the bits of `self` whose index `n` higher is set in `other`,
+    /// i.e. `self &= other >> n`.
+    pub fn and_shifted(&mut self, other: &BitSet, n: usize) {
+        match (self, other) {
+            (BitSet::Small(a), BitSet::Small(b)) => *a &= b.checked_shr(n as u32).unwrap_or(0),
+            (BitSet::Medium(a), BitSet::Medium(b)) => *a &= b.checked_shr(n as u32).unwrap_or(0),
+            (BitSet::Large(a), BitSet::Large(b)) => {
+                let word_shift = n / 64;
+                let bit_shift = n % 64;
+                for (i, word) in a.iter_mut().enumerate() {
+                    let lo = b.get(i + word_shift).copied().unwrap_or(0);
+                    let hi = b.get(i + word_shift + 1).copied().unwrap_or(0);
+                    let shifted = if bit_shift == 0 {
+                        lo
+                    } else {
+                        lo >> bit_shift | hi << (64 - bit_shift)
+                    };
+                    *word &= shifted;
+                }
+            }
+            _ => panic!("BitSet sizes do not match"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A set of `len` bits with exactly `bits` set
+    fn with_bits(len: usize, bits: &[usize]) -> BitSet {
+        let mut set = BitSet::new(len);
+        for &i in bits {
+            set.set(i);
+        }
+        set
+    }
+
+    /// The indices of every bit set in the first `len`
+    fn bits(set: &BitSet, len: usize) -> Vec<usize> {
+        (0..len).filter(|&i| set.get(i)).collect()
+    }
+
+    #[test]
+    fn size_picks_the_smallest_storage() {
+        assert!(matches!(BitSet::new(64), BitSet::Small(_)));
+        assert!(matches!(BitSet::new(65), BitSet::Medium(_)));
+        assert!(matches!(BitSet::new(128), BitSet::Medium(_)));
+        assert!(matches!(BitSet::new(129), BitSet::Large(_)));
+    }
+
+    #[test]
+    fn set_and_unset_at_the_edges_of_words() {
+        for len in [64, 128, 300] {
+            let edges: Vec<usize> = [0, 63, 64, 127, 128, 191, 192, 299]
+                .into_iter()
+                .filter(|&i| i < len)
+                .collect();
+            let mut set = with_bits(len, &edges);
+            assert_eq!(bits(&set, len), edges);
+            for &i in &edges {
+                set.unset(i);
+            }
+            assert!(set.is_empty());
+        }
+    }
+
+    #[test]
+    fn and_shifted_small() {
+        let mut set = with_bits(64, &[0, 5, 62]);
+        set.and_shifted(&with_bits(64, &[6, 63]), 1);
+        assert_eq!(bits(&set, 64), [5, 62]);
+
+        // Shifting by the whole word leaves nothing rather than overflowing
+        let mut set = with_bits(64, &[0]);
+        set.and_shifted(&with_bits(64, &[63]), 64);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn and_shifted_medium() {
+        // Bits move between the two halves of the u128
+        let mut set = with_bits(128, &[10, 63, 64]);
+        set.and_shifted(&with_bits(128, &[74, 127]), 64);
+        assert_eq!(bits(&set, 128), [10, 63]);
+
+        let mut set = with_bits(128, &[0]);
+        set.and_shifted(&with_bits(128, &[127]), 128);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn and_shifted_large() {
+        let all: Vec<usize> = (0..300).collect();
+        for n in [1, 63, 64, 65, 127, 128, 129, 200, 299, 300, 400] {
+            // A single bit lands `n` lower, carried across word boundaries
+            let mut set = with_bits(300, &all);
+            set.and_shifted(&with_bits(300, &[299, 192, 130, 64]), n);
+            let expected: Vec<usize> = [64, 130, 192, 299]
+                .into_iter()
+                .filter_map(|i: usize| i.checked_sub(n))
+                .collect();
+            assert_eq!(bits(&set, 300), expected, "shift by {n}");
+        }
+    }
+
+    #[test]
+    fn and_shifted_matches_a_plain_shift() {
+        // The same bits shifted the same way agree whatever the storage
+        let pattern = [0, 1, 2, 7, 8, 9, 30, 31, 32, 33, 62, 63];
+        for n in [0, 1, 2, 9, 31, 33, 62, 63] {
+            let expected: Vec<usize> = pattern
+                .into_iter()
+                .filter(|&i| pattern.contains(&(i + n)))
+                .collect();
+            for len in [64, 128, 300] {
+                let mut set = with_bits(len, &pattern);
+                set.and_shifted(&with_bits(len, &pattern), n);
+                assert_eq!(bits(&set, len), expected, "{len} bits shifted by {n}");
+            }
+        }
+    }
+}
@@ -1,135 +1,666 @@
-use macroquad::prelude::*;
-use ndarray::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{Turn, LEFT_BUFFER};
+use crate::{bitboard::BitSet, Turn, MAX_PLAYERS};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Pieces {
-    P1,
-    P2,
+    Player(Turn),
     Empty,
+    /// A cell that is not part of the board, which nobody can play in and
+    /// which breaks any line through it
+    Blocked,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A way of taking a turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Drop a piece into the column
+    Drop(usize),
+    /// Take one of your own pieces off the bottom of the column, letting the
+    /// rest of the column fall down. Only allowed by the PopOut rules.
+    Pop(usize),
+    /// Put a piece straight into the empty cell at `(row, col)`, when there
+    /// is no gravity
+    Put(usize, usize),
+}
+
+impl Action {
+    pub fn col(&self) -> usize {
+        match *self {
+            Action::Drop(col) | Action::Pop(col) | Action::Put(_, col) => col,
+        }
+    }
+}
+
+/// The ways a game can differ from standard Connect X
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// Pieces fall to the lowest empty cell of their column. Without gravity
+    /// they can go in any empty cell, as in Gomoku.
+    pub gravity: bool,
+    /// Players may pop one of their own pieces off the bottom instead of
+    /// dropping one. Popping needs gravity.
+    pub popout: bool,
+    /// Lines carry on from the last column into the first
+    pub wrap_horizontal: bool,
+    /// Lines carry on from the bottom row into the top
+    pub wrap_vertical: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            gravity: true,
+            popout: false,
+            wrap_horizontal: false,
+            wrap_vertical: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GameState {
     OnGoing,
-    P1Win,
-    P2Win,
+    Win(Turn),
     Tie,
 }
 
-const P1_COLOR: Color = Color::new(0.90, 0.16, 0.22, 1.00);
-const P1_COLOR_TRANS: Color = Color::new(0.90, 0.16, 0.22, 0.50);
-const P2_COLOR: Color = Color::new(0.99, 0.98, 0.00, 1.00);
-const P2_COLOR_TRANS: Color = Color::new(0.99, 0.98, 0.00, 0.50);
+/// Generates the Zobrist key for `piece` sitting on bit `index`.
+///
+/// Keys are derived with SplitMix64 rather than stored in a table, so even the
+/// largest boards cost no memory.
+fn zobrist_key(index: usize, piece: Pieces) -> u64 {
+    let piece = match piece {
+        Pieces::Player(turn) => turn.index() as u64 + 1,
+        Pieces::Blocked => MAX_PLAYERS as u64 + 1,
+        Pieces::Empty => return 0,
+    };
+
+    let mut z =
+        (index as u64 * (MAX_PLAYERS as u64 + 2) + piece).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Directions a line can run in, as (row, col) steps
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// A run of at least `x_to_win` pieces of one player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinningLine {
+    pub piece: Pieces,
+    /// The (row, col) of the first cell of the run
+    pub start: (usize, usize),
+    /// The (row, col) step from each cell of the run to the next: right, down,
+    /// down-right or down-left
+    pub direction: (isize, isize),
+    pub len: usize,
+    /// The rows and columns of the board, which a run on a wrapping board
+    /// continues around
+    pub size: (usize, usize),
+}
+
+impl WinningLine {
+    /// The (row, col) of every cell in the run, from `start` onwards.
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let (row, col) = (self.start.0 as isize, self.start.1 as isize);
+        let (rows, cols) = (self.size.0 as isize, self.size.1 as isize);
+        let (dr, dc) = self.direction;
+        (0..self.len as isize).map(move |i| {
+            (
+                (row + dr * i).rem_euclid(rows) as usize,
+                (col + dc * i).rem_euclid(cols) as usize,
+            )
+        })
+    }
 
+    pub fn end(&self) -> (usize, usize) {
+        self.cells().last().unwrap()
+    }
+}
+
+/// The playing field, stored as one bitboard per player.
+///
+/// Cell `(row, col)` lives at bit `row * (cols + 1) + col`. The extra column on
+/// every row is always empty so that shifting a whole layer never carries a
+/// line from the end of one row into the start of the next.
+///
+/// Under the PopOut rules a full board isn't the end of the game while the
+/// player to move can still pop, and the game is a tie instead once the same
+/// position comes up for the third time.
+///
+/// Blocked cells give the board its shape and stay put when it is reset. With
+/// gravity, blocked cells at the top of a column lower its opening, and a
+/// piece dropped in comes to rest on the first blocked cell or piece below.
 #[derive(Debug, Clone)]
 pub struct Board {
     rows: usize,
     cols: usize,
     x_to_win: usize,
-    board: Array1<Pieces>,
+    rules: Rules,
+    /// Every position reached by a move under the PopOut rules along with who
+    /// made the move, to spot repetitions
+    positions: Vec<(u64, Turn)>,
+    /// One layer for each player's pieces, in turn order
+    layers: Vec<BitSet>,
+    /// The cells that are blocked off
+    blocked: BitSet,
+    /// Number of pieces in each column, which with gravity are stacked on the
+    /// column's floor
+    heights: Vec<usize>,
+    /// The number of blocked cells at the top of each column
+    ceilings: Vec<usize>,
+    /// The row below the lowest cell a piece can fall to in each column, which
+    /// is the first blocked cell under the ceiling or else the bottom of the
+    /// board
+    floors: Vec<usize>,
+    /// Zobrist hash of the pieces and blocked cells on the board
+    hash: u64,
+    /// The result of the game so far, kept up to date as pieces are placed so
+    /// that asking for it is free
+    state: GameState,
+    /// The bit index of the piece that won and how many pieces were on the
+    /// board after it, so undoing that move can skip rescanning the board
+    winning_move: Option<(usize, usize)>,
 }
 
 impl Board {
+    /// An empty board for two players.
     pub fn new(rows: usize, cols: usize) -> Self {
+        Board::with_players(rows, cols, 2)
+    }
+
+    pub fn with_players(rows: usize, cols: usize, players: usize) -> Self {
         Board {
             rows,
             cols,
             x_to_win: 0,
-            board: Array1::from_elem(rows * cols, Pieces::Empty),
+            rules: Rules::default(),
+            positions: vec![],
+            layers: vec![BitSet::new(rows * (cols + 1)); players],
+            blocked: BitSet::new(rows * (cols + 1)),
+            heights: vec![0; cols],
+            ceilings: vec![0; cols],
+            floors: vec![rows; cols],
+            hash: 0,
+            state: GameState::OnGoing,
+            winning_move: None,
         }
     }
 
     pub fn verify(&mut self, rows: usize, cols: usize, x_to_win: usize) {
         if self.rows != rows || self.cols != cols {
-            self.rows = rows;
-            self.cols = cols;
-            self.board = Array1::from_elem(rows * cols, Pieces::Empty);
+            *self = Board {
+                x_to_win: self.x_to_win,
+                rules: self.rules,
+                ..Board::with_players(rows, cols, self.players())
+            };
         }
         if self.x_to_win != x_to_win {
             self.x_to_win = x_to_win;
+            self.refresh_state();
+        }
+    }
+
+    /// Changes the number of players, which empties the board but keeps its
+    /// shape.
+    pub fn set_players(&mut self, players: usize) {
+        if self.players() != players {
+            let blocked = self.blocked_cells();
+            *self = Board {
+                x_to_win: self.x_to_win,
+                rules: self.rules,
+                ..Board::with_players(self.rows, self.cols, players)
+            };
+            self.set_shape(&blocked);
+        }
+    }
+
+    /// Empties the board and blocks off exactly the cells in `blocked`.
+    pub fn set_shape(&mut self, blocked: &[(usize, usize)]) {
+        self.blocked.clear();
+        for &(row, col) in blocked {
+            let i = self.index(row, col);
+            self.blocked.set(i);
+        }
+        for col in 0..self.cols {
+            self.update_floor(col);
+        }
+        self.reset();
+    }
+
+    /// Every blocked cell, reading from the top
+    pub fn blocked_cells(&self) -> Vec<(usize, usize)> {
+        (0..self.rows)
+            .flat_map(|row| (0..self.cols).map(move |col| (row, col)))
+            .filter(|&(row, col)| self.blocked.get(self.index(row, col)))
+            .collect()
+    }
+
+    /// Works out where pieces enter and come to rest in `col` from its blocked
+    /// cells.
+    fn update_floor(&mut self, col: usize) {
+        let blocked = |row: usize| self.blocked.get(self.index(row, col));
+        let ceiling = (0..self.rows).take_while(|&row| blocked(row)).count();
+        let floor = (ceiling..self.rows)
+            .find(|&row| blocked(row))
+            .unwrap_or(self.rows);
+        self.ceilings[col] = ceiling;
+        self.floors[col] = floor;
+    }
+
+    /// Changes the rules, which empties the board.
+    pub fn set_rules(&mut self, rules: Rules) {
+        if self.rules != rules {
+            self.rules = rules;
+            self.reset();
         }
     }
 
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
     pub fn reset(&mut self) {
-        self.board = Array1::from_elem(self.rows * self.cols, Pieces::Empty)
+        self.layers.iter_mut().for_each(BitSet::clear);
+        self.heights.fill(0);
+        self.positions.clear();
+        self.hash = self
+            .blocked_cells()
+            .into_iter()
+            .fold(0, |hash, (row, col)| {
+                hash ^ zobrist_key(self.index(row, col), Pieces::Blocked)
+            });
+        // A board can be all blocked cells
+        self.refresh_state();
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn x_to_win(&self) -> usize {
+        self.x_to_win
+    }
+
+    pub fn players(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn index(&self, row: usize, col: usize) -> usize {
+        row * (self.cols + 1) + col
+    }
+
+    /// A Zobrist hash of the current position, kept up to date as pieces are
+    /// placed and removed.
+    pub fn hash(&self) -> u64 {
+        self.hash
     }
 
     pub fn piece_at(&self, row: usize, col: usize) -> Pieces {
-        self.board[[row * self.cols + col]]
+        let i = self.index(row, col);
+        if self.blocked.get(i) {
+            return Pieces::Blocked;
+        }
+        match self.layers.iter().position(|layer| layer.get(i)) {
+            Some(player) => Pieces::Player(Turn::from_index(player)),
+            None => Pieces::Empty,
+        }
     }
 
     pub fn set_piece(&mut self, row: usize, col: usize, piece: Pieces) {
-        self.board[[row * self.cols + col]] = piece
+        let i = self.index(row, col);
+        let was_empty = self.piece_at(row, col) == Pieces::Empty;
+        self.hash ^= zobrist_key(i, self.piece_at(row, col));
+        self.hash ^= zobrist_key(i, piece);
+        self.layers.iter_mut().for_each(|layer| layer.unset(i));
+        self.blocked.unset(i);
+        match piece {
+            Pieces::Player(turn) => self.layers[turn.index()].set(i),
+            Pieces::Blocked => self.blocked.set(i),
+            Pieces::Empty => {}
+        }
+        // The moves that led here no longer do
+        self.positions.clear();
+
+        self.heights[col] = (0..self.rows)
+            .filter(|&row| matches!(self.piece_at(row, col), Pieces::Player(_)))
+            .count();
+        self.update_floor(col);
+
+        // Filling an empty cell can only add lines through it, anything else
+        // could have broken a line so the whole board is checked again
+        if was_empty && matches!(piece, Pieces::Player(_)) && self.state == GameState::OnGoing {
+            self.update_state(row, col);
+        } else {
+            self.refresh_state();
+        }
+    }
+
+    /// Fills in the empty cells of a new board all at once, as when reading
+    /// a position. Unlike [`Board::set_piece`] for each cell, the columns are
+    /// measured and the result checked only once at the end.
+    pub(crate) fn set_cells(&mut self, cells: &[(usize, usize, Pieces)]) {
+        for &(row, col, piece) in cells {
+            debug_assert_eq!(self.piece_at(row, col), Pieces::Empty);
+            let i = self.index(row, col);
+            self.hash ^= zobrist_key(i, piece);
+            match piece {
+                Pieces::Player(turn) => {
+                    self.layers[turn.index()].set(i);
+                    self.heights[col] += 1;
+                }
+                Pieces::Blocked => self.blocked.set(i),
+                Pieces::Empty => {}
+            }
+        }
+        self.positions.clear();
+
+        for col in 0..self.cols {
+            self.update_floor(col);
+        }
+        self.refresh_state();
     }
 
     pub fn place(&mut self, col: usize, turn: &Turn) -> bool {
-        for row in (0..self.rows).rev() {
-            if self.piece_at(row, col) == Pieces::Empty {
-                self.set_piece(
-                    row,
-                    col,
-                    match turn {
-                        Turn::Player1 => Pieces::P1,
-                        Turn::Player2 => Pieces::P2,
-                    },
-                );
+        self.drop_piece(col, turn).is_some()
+    }
+
+    /// Drops a piece into `col` like [`Board::place`], returning the row it
+    /// landed in or `None` if the column is full or there is no gravity.
+    pub fn drop_piece(&mut self, col: usize, turn: &Turn) -> Option<usize> {
+        if !self.rules.gravity || !self.has_room(col) {
+            return None;
+        }
+
+        let row = self.floors[col] - 1 - self.heights[col];
+        self.fill(row, col, turn);
+        Some(row)
+    }
+
+    /// The row below the lowest cell a piece dropped into `col` can reach,
+    /// which is the bottom of the board unless a blocked cell is in the way
+    pub fn floor(&self, col: usize) -> usize {
+        self.floors[col]
+    }
+
+    /// Whether a piece dropped into `col` has somewhere to land
+    fn has_room(&self, col: usize) -> bool {
+        self.ceilings[col] + self.heights[col] < self.floors[col]
+    }
+
+    /// Whether there is nowhere left to play, ignoring pops
+    fn is_full(&self) -> bool {
+        if self.rules.gravity {
+            (0..self.cols).all(|col| !self.has_room(col))
+        } else {
+            (0..self.rows)
+                .all(|row| (0..self.cols).all(|col| self.piece_at(row, col) != Pieces::Empty))
+        }
+    }
 
-                return true;
+    /// Puts a piece straight into the empty cell at `(row, col)`, returning
+    /// false if the cell is taken or there is gravity.
+    pub fn put_piece(&mut self, row: usize, col: usize, turn: &Turn) -> bool {
+        if self.rules.gravity
+            || row >= self.rows
+            || col >= self.cols
+            || self.piece_at(row, col) != Pieces::Empty
+        {
+            return false;
+        }
+
+        self.fill(row, col, turn);
+        true
+    }
+
+    /// Gives the empty cell at `(row, col)` to `turn` as their move.
+    fn fill(&mut self, row: usize, col: usize, turn: &Turn) {
+        let i = self.index(row, col);
+        self.layers[turn.index()].set(i);
+        self.hash ^= zobrist_key(i, Pieces::Player(*turn));
+        self.heights[col] += 1;
+        if self.rules.popout {
+            self.positions.push((self.hash, *turn));
+        }
+
+        // Once decided the result stands, as with a piece played after a win
+        if self.state == GameState::OnGoing {
+            self.update_state(row, col);
+            if self.rules.popout {
+                self.update_popout_state(turn);
             }
         }
-        false
     }
 
-    pub fn mouse_hover(&self, piece_size: f32, psn: (f32, f32), turn: &Turn) -> Option<usize> {
-        let x = psn.0 - LEFT_BUFFER;
-        if x < 0.0 || x > self.cols as f32 * piece_size {
-            return None;
+    /// Takes `turn`'s piece off the bottom of `col` and lets the rest of the
+    /// column fall down, returning false if that isn't allowed.
+    pub fn pop(&mut self, col: usize, turn: &Turn) -> bool {
+        if !self.can_pop(col, turn) {
+            return false;
         }
 
-        let col = (x / piece_size) as usize;
+        // Every piece in the column moves down a row, starting from the bottom
+        let (top, floor) = (self.floors[col] - self.heights[col], self.floors[col]);
+        for row in (top..floor).rev() {
+            let above = if row > top {
+                self.piece_at(row - 1, col)
+            } else {
+                Pieces::Empty
+            };
+            self.set_cell(row, col, above);
+        }
+        self.heights[col] -= 1;
+        self.positions.push((self.hash, *turn));
+        // Undoing a move only knows how to skip the rescan for drops
+        self.winning_move = None;
 
-        let x_pos = LEFT_BUFFER + col as f32 * piece_size;
-        let height = self.rows as f32 * piece_size;
-        let color = match turn {
-            Turn::Player1 => P1_COLOR_TRANS,
-            Turn::Player2 => P2_COLOR_TRANS,
-        };
+        if self.state == GameState::OnGoing {
+            // Only lines through the column can have been made, and the
+            // player who popped wins even if someone else got one too
+            let mut winners: Vec<Turn> = (top + 1..floor)
+                .filter(|&row| self.check_win_at(row, col))
+                .filter_map(|row| match self.piece_at(row, col) {
+                    Pieces::Player(turn) => Some(turn),
+                    Pieces::Empty | Pieces::Blocked => None,
+                })
+                .collect();
+            winners.sort_by_key(|winner| {
+                (winner.index() + self.players() - turn.index()) % self.players()
+            });
+            match winners.first() {
+                Some(&winner) => self.state = GameState::Win(winner),
+                None => self.update_popout_state(turn),
+            }
+        }
 
-        draw_rectangle(x_pos, 0.0, piece_size, height, color);
+        true
+    }
+
+    /// Puts `turn`'s piece back at the bottom of `col`, reversing a call to
+    /// [`Board::pop`].
+    pub fn unpop(&mut self, col: usize, turn: &Turn) {
+        let (top, floor) = (self.floors[col] - 1 - self.heights[col], self.floors[col]);
+        for row in top..floor {
+            let below = if row + 1 < floor {
+                self.piece_at(row + 1, col)
+            } else {
+                Pieces::Player(*turn)
+            };
+            self.set_cell(row, col, below);
+        }
+        self.heights[col] += 1;
+        self.positions.pop();
+
+        self.state = match self.state {
+            // The position before was one a move was made from, so it was
+            // still going
+            GameState::OnGoing | GameState::Tie => GameState::OnGoing,
+            // Only a line that was there before the pop decides it
+            GameState::Win(_) => match self.scan_state() {
+                GameState::Tie => GameState::OnGoing,
+                state => state,
+            },
+        };
+        self.winning_move = None;
+    }
 
-        Some(col)
+    /// Whether `turn` may pop their piece off the bottom of `col`.
+    pub fn can_pop(&self, col: usize, turn: &Turn) -> bool {
+        self.rules.popout
+            && self.rules.gravity
+            && self.heights[col] > 0
+            && self.piece_at(self.floors[col] - 1, col) == Pieces::Player(*turn)
     }
 
-    pub fn draw(&self, piece_size: f32) {
-        for i in 0..self.rows {
-            for j in 0..self.cols {
-                let mut x = LEFT_BUFFER + j as f32 * piece_size;
-                let mut y = i as f32 * piece_size;
-                draw_rectangle(x, y, piece_size - 1.0, piece_size - 1.0, GRAY);
+    /// Makes `action` for `turn`, returning false if it isn't allowed.
+    pub fn play(&mut self, action: Action, turn: &Turn) -> bool {
+        match action {
+            Action::Drop(col) => self.place(col, turn),
+            Action::Pop(col) => self.pop(col, turn),
+            Action::Put(row, col) => self.put_piece(row, col, turn),
+        }
+    }
 
-                x += piece_size / 2.0;
-                y += piece_size / 2.0;
-                match self.piece_at(i, j) {
-                    Pieces::P1 => draw_circle(x, y, piece_size / 2.5, P1_COLOR),
-                    Pieces::P2 => draw_circle(x, y, piece_size / 2.5, P2_COLOR),
-                    Pieces::Empty => draw_circle(x, y, piece_size / 2.5, WHITE),
-                }
+    /// Reverses `action` made by `turn`, which must have been the last thing
+    /// done to the board.
+    pub fn take_back(&mut self, action: Action, turn: &Turn) {
+        match action {
+            Action::Drop(col) => {
+                self.undo(col);
+            }
+            Action::Pop(col) => self.unpop(col, turn),
+            Action::Put(row, col) => {
+                self.lift(row, col);
             }
         }
     }
 
-    pub fn moves(&self) -> Vec<usize> {
-        let mut moves = vec![];
+    /// Sets a cell without touching the heights or the result, keeping the
+    /// hash up to date.
+    fn set_cell(&mut self, row: usize, col: usize, piece: Pieces) {
+        let i = self.index(row, col);
+        self.hash ^= zobrist_key(i, self.piece_at(row, col));
+        self.hash ^= zobrist_key(i, piece);
+        self.layers.iter_mut().for_each(|layer| layer.unset(i));
+        if let Pieces::Player(turn) = piece {
+            self.layers[turn.index()].set(i);
+        }
+    }
 
-        for col in 0..self.cols {
-            if self.piece_at(0, col) == Pieces::Empty {
-                moves.push(col);
+    /// Applies the PopOut ways of drawing after `turn` moved without winning:
+    /// the third time a position comes up, or the next player having no move
+    /// at all. A full board on its own doesn't end the game.
+    fn update_popout_state(&mut self, turn: &Turn) {
+        if matches!(self.state, GameState::Win(_)) {
+            return;
+        }
+
+        let next = turn.next(self.players());
+        let position = (self.hash, *turn);
+        let repeats = self.positions.iter().filter(|&&p| p == position).count();
+        self.state = if repeats >= 3 || self.moves(&next).is_empty() {
+            GameState::Tie
+        } else {
+            GameState::OnGoing
+        };
+    }
+
+    /// Works out the result after a piece went into the empty cell at
+    /// `(row, col)` of an undecided game.
+    fn update_state(&mut self, row: usize, col: usize) {
+        self.state = if self.check_win_at(row, col) {
+            self.winning_move = Some((self.index(row, col), self.piece_count()));
+            match self.piece_at(row, col) {
+                Pieces::Player(turn) => GameState::Win(turn),
+                Pieces::Empty | Pieces::Blocked => unreachable!(),
+            }
+        } else if self.is_full() {
+            GameState::Tie
+        } else {
+            GameState::OnGoing
+        };
+    }
+
+    /// Removes the top piece from `col`, reversing a call to [`Board::place`].
+    pub fn undo(&mut self, col: usize) -> bool {
+        self.heights[col] > 0 && self.lift(self.floors[col] - self.heights[col], col)
+    }
+
+    /// Removes the piece at `(row, col)`, reversing the move that filled it.
+    fn lift(&mut self, row: usize, col: usize) -> bool {
+        let i = self.index(row, col);
+        let piece = self.piece_at(row, col);
+        if !matches!(piece, Pieces::Player(_)) {
+            return false;
+        }
+
+        self.heights[col] -= 1;
+        self.hash ^= zobrist_key(i, piece);
+        self.layers.iter_mut().for_each(|layer| layer.unset(i));
+        if self.rules.popout {
+            self.positions.pop();
+        }
+
+        match self.state {
+            // A board with no lines can't gain one by losing a piece, and
+            // can't be full any more
+            GameState::OnGoing | GameState::Tie => self.state = GameState::OnGoing,
+            // Taking back the winning move with nothing played since returns
+            // to the undecided position before it
+            GameState::Win(_) if self.winning_move == Some((i, self.piece_count() + 1)) => {
+                self.state = GameState::OnGoing;
+                self.winning_move = None;
             }
+            GameState::Win(_) => self.refresh_state(),
+        }
+
+        true
+    }
+
+    /// The number of pieces on the board
+    pub fn piece_count(&self) -> usize {
+        self.heights.iter().sum()
+    }
+
+    /// The first piece, reading from the top, with an empty cell below it or
+    /// under a blocked cell that it couldn't have fallen past, neither of which
+    /// can happen with gravity
+    pub fn floating_piece(&self) -> Option<(usize, usize)> {
+        (0..self.rows)
+            .flat_map(|row| (0..self.cols).map(move |col| (row, col)))
+            .find(|&(row, col)| {
+                matches!(self.piece_at(row, col), Pieces::Player(_))
+                    && (row >= self.floors[col]
+                        || row + 1 < self.rows && self.piece_at(row + 1, col) == Pieces::Empty)
+            })
+    }
+
+    /// Everything `turn` can do: a drop into each column with room, and under
+    /// the PopOut rules a pop of each of their pieces on the bottom row.
+    /// Without gravity it is every empty cell instead.
+    pub fn moves(&self, turn: &Turn) -> Vec<Action> {
+        if !self.rules.gravity {
+            return (0..self.rows)
+                .flat_map(|row| (0..self.cols).map(move |col| (row, col)))
+                .filter(|&(row, col)| self.piece_at(row, col) == Pieces::Empty)
+                .map(|(row, col)| Action::Put(row, col))
+                .collect();
         }
 
-        moves
+        let drops = (0..self.cols)
+            .filter(|&col| self.has_room(col))
+            .map(Action::Drop);
+        let pops = (0..self.cols)
+            .filter(|&col| self.can_pop(col, turn))
+            .map(Action::Pop);
+        drops.chain(pops).collect()
     }
 
     pub fn result(&self, col: usize, turn: &Turn) -> Self {
@@ -138,104 +669,171 @@ impl Board {
         new_board
     }
 
-    pub fn game_state(&self) -> GameState {
-        let mut full = true;
+    /// Checks if the top piece of `col` is part of a winning line.
+    pub fn top_piece_wins(&self, col: usize) -> bool {
+        self.heights[col] > 0 && self.check_win_at(self.floors[col] - self.heights[col], col)
+    }
 
-        // Check if top row is filled
-        for col in 0..self.cols {
-            if self.piece_at(0, col) == Pieces::Empty {
-                full = false;
-                break;
+    /// Checks if the piece at `(row, col)` is part of a winning line.
+    ///
+    /// Only the lines running through that cell are inspected, so this is the
+    /// cheap way to test the result of the move that was just made.
+    pub fn check_win_at(&self, row: usize, col: usize) -> bool {
+        let piece = self.piece_at(row, col);
+        if !matches!(piece, Pieces::Player(_)) {
+            return false;
+        }
+
+        DIRECTIONS.iter().any(|&(dr, dc)| {
+            let count = 1
+                + self.run_length(row, col, dr, dc, piece)
+                + self.run_length(row, col, -dr, -dc, piece);
+            count >= self.x_to_win
+        })
+    }
+
+    /// The cell `steps` steps of `dir` away from `(row, col)`, going around
+    /// the edges that the rules join up, or `None` if that is off the board.
+    fn offset(
+        &self,
+        row: usize,
+        col: usize,
+        dir: (isize, isize),
+        steps: isize,
+    ) -> Option<(usize, usize)> {
+        let wrap = |pos: isize, len: usize, wraps: bool| {
+            if wraps {
+                Some(pos.rem_euclid(len as isize) as usize)
+            } else if (0..len as isize).contains(&pos) {
+                Some(pos as usize)
+            } else {
+                None
+            }
+        };
+        Some((
+            wrap(
+                row as isize + dir.0 * steps,
+                self.rows,
+                self.rules.wrap_vertical,
+            )?,
+            wrap(
+                col as isize + dir.1 * steps,
+                self.cols,
+                self.rules.wrap_horizontal,
+            )?,
+        ))
+    }
+
+    /// Counts the pieces matching `piece` when walking away from `(row, col)`,
+    /// stopping once a line long enough to win has been seen.
+    fn run_length(&self, row: usize, col: usize, dr: isize, dc: isize, piece: Pieces) -> usize {
+        let mut count = 0;
+        while count + 1 < self.x_to_win {
+            match self.offset(row, col, (dr, dc), count as isize + 1) {
+                Some((r, c)) if self.piece_at(r, c) == piece => count += 1,
+                _ => break,
             }
         }
+        count
+    }
 
-        // Check all diagonal and cardinal direction
-        for i in 0..self.rows {
-            for j in 0..self.cols {
-                let cur_piece = self.piece_at(i, j);
-                if cur_piece == Pieces::Empty {
-                    continue;
-                }
+    /// Checks if `layer` contains a line of `x_to_win` pieces in any direction.
+    fn has_line(&self, layer: &BitSet) -> bool {
+        // Shifting a layer can't follow a line around the edges, so on a
+        // wrapping board every piece is checked instead
+        if self.rules.wrap_horizontal || self.rules.wrap_vertical {
+            return (0..self.rows)
+                .flat_map(|row| (0..self.cols).map(move |col| (row, col)))
+                .any(|(row, col)| layer.get(self.index(row, col)) && self.check_win_at(row, col));
+        }
 
-                // Check right
-                if j + self.x_to_win <= self.cols {
-                    let mut count = 1;
-                    for col in j + 1..self.cols {
-                        if self.piece_at(i, col) == cur_piece {
-                            count += 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    if count >= self.x_to_win {
-                        return if cur_piece == Pieces::P1 {
-                            GameState::P1Win
-                        } else {
-                            GameState::P2Win
-                        };
-                    }
-                }
+        let stride = self.cols + 1;
+        [1, stride, stride + 1, stride - 1].iter().any(|&shift| {
+            let mut line = layer.clone();
+            for i in 1..self.x_to_win {
+                line.and_shifted(layer, shift * i);
+            }
+            !line.is_empty()
+        })
+    }
 
-                // Check down
-                if i + self.x_to_win <= self.rows {
-                    let mut count = 1;
-                    for row in i + 1..self.rows {
-                        if self.piece_at(row, j) == cur_piece {
-                            count += 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    if count >= self.x_to_win {
-                        return if cur_piece == Pieces::P1 {
-                            GameState::P1Win
-                        } else {
-                            GameState::P2Win
-                        };
-                    }
-                }
+    /// Every run long enough to win, each reported once at its full length.
+    /// Usually there is only one, but a single move can complete several.
+    pub fn winning_lines(&self) -> Vec<WinningLine> {
+        let mut lines = vec![];
+        if !self.layers.iter().any(|layer| self.has_line(layer)) {
+            return lines;
+        }
 
-                // Check up diagonal
-                if i as i32 - self.x_to_win as i32 >= -1 && j + self.x_to_win <= self.cols {
-                    let mut count = 1;
-                    for offset in 1..self.x_to_win {
-                        if self.piece_at(i - offset, j + offset) == cur_piece {
-                            count += 1;
-                        } else {
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let piece = self.piece_at(row, col);
+                if !matches!(piece, Pieces::Player(_)) {
+                    continue;
+                }
+                for &dir in &DIRECTIONS {
+                    // On a wrapping board a run can come all the way back round
+                    let mut len = 1;
+                    let mut looped = false;
+                    while let Some((r, c)) = self.offset(row, col, dir, len as isize) {
+                        if (r, c) == (row, col) {
+                            looped = true;
                             break;
                         }
-                    }
-                    if count >= self.x_to_win {
-                        return if cur_piece == Pieces::P1 {
-                            GameState::P1Win
-                        } else {
-                            GameState::P2Win
-                        };
-                    }
-                }
-                // Check down diagonal
-                if i + self.x_to_win <= self.rows && j + self.x_to_win <= self.cols {
-                    let mut count = 1;
-                    for offset in 1..self.x_to_win {
-                        if self.piece_at(i + offset, j + offset) == cur_piece {
-                            count += 1;
-                        } else {
+                        if self.piece_at(r, c) != piece {
                             break;
                         }
+                        len += 1;
                     }
-                    if count >= self.x_to_win {
-                        return if cur_piece == Pieces::P1 {
-                            GameState::P1Win
-                        } else {
-                            GameState::P2Win
-                        };
+
+                    // Only measure from the first cell of each run, which for a
+                    // loop is the one found first
+                    let first = if looped {
+                        (0..len as isize).all(|i| self.offset(row, col, dir, i) >= Some((row, col)))
+                    } else {
+                        self.offset(row, col, dir, -1)
+                            .is_none_or(|(r, c)| self.piece_at(r, c) != piece)
+                    };
+                    if first && len >= self.x_to_win {
+                        lines.push(WinningLine {
+                            piece,
+                            start: (row, col),
+                            direction: dir,
+                            len,
+                            size: (self.rows, self.cols),
+                        });
                     }
                 }
             }
         }
+        lines
+    }
+
+    /// The result of the game so far. This is cached on the board, so it is
+    /// free to call as often as needed.
+    pub fn game_state(&self) -> GameState {
+        self.state
+    }
+
+    /// Whether any player has made a line, which after a move is exactly
+    /// whether that move won.
+    pub fn has_winner(&self) -> bool {
+        matches!(self.state, GameState::Win(_))
+    }
+
+    /// Works out the result again by checking the whole board.
+    fn refresh_state(&mut self) {
+        self.state = self.scan_state();
+        self.winning_move = None;
+    }
+
+    fn scan_state(&self) -> GameState {
+        if let Some(player) = self.layers.iter().position(|layer| self.has_line(layer)) {
+            return GameState::Win(Turn::from_index(player));
+        }
 
         // If board is full and no one has won, then its a tie
-        if full {
+        if self.is_full() {
             return GameState::Tie;
         }
 
@@ -244,97 +842,600 @@ impl Board {
     }
 
     /// Code modified from https://github.com/KeithGalli/Connect4-Python
+    ///
+    /// With more than two players every other player counts as the opponent.
     pub fn score_position(&self, turn: &Turn) -> i32 {
         let mut score: i32 = 0;
 
-        let cur_piece = match turn {
-            Turn::Player1 => Pieces::P1,
-            Turn::Player2 => Pieces::P2,
-        };
+        let cur_piece = Pieces::Player(*turn);
 
         let x_m1 = self.x_to_win - 1;
 
         // Score center column
-        let center_count = self
-            .board
-            .slice(s![(self.cols / 2)..;self.cols])
-            .iter()
-            .filter(|&p| *p == cur_piece)
+        let center_count = (0..self.rows)
+            .filter(|&r| self.piece_at(r, self.cols / 2) == cur_piece)
             .count() as i32;
         score += center_count * 3;
 
-        // Score Horizontal
-        for r in 0..self.rows {
-            let r_num = r * self.cols;
-            let row_array = self.board.slice(s![r_num..(r_num + self.cols)]);
-            for c in 0..(self.cols - x_m1) {
-                let window = row_array.slice(s![c..c + self.x_to_win]);
-                score += evaluate_window(&window, cur_piece, self.x_to_win);
+        // Score every window in each direction that fits on the board, which
+        // includes those going around the edges of a wrapping board
+        for dir in DIRECTIONS {
+            for r in 0..self.rows {
+                for c in 0..self.cols {
+                    if self.offset(r, c, dir, x_m1 as isize).is_some() {
+                        score += self.evaluate_window(r, c, dir, cur_piece);
+                    }
+                }
+            }
+        }
+
+        score
+    }
+
+    /// Code modified from https://github.com/KeithGalli/Connect4-Python
+    ///
+    /// Scores the `x_to_win` cells starting at `(row, col)` and stepping by `dir`.
+    fn evaluate_window(&self, row: usize, col: usize, dir: (isize, isize), piece: Pieces) -> i32 {
+        let mut score = 0;
+        let x = self.x_to_win;
+
+        let mut piece_count = 0;
+        let mut opp_count = 0;
+        for i in 0..x as isize {
+            let (r, c) = self
+                .offset(row, col, dir, i)
+                .expect("the window fits on the board");
+            match self.piece_at(r, c) {
+                cell if cell == piece => piece_count += 1,
+                Pieces::Empty => {}
+                // Nobody can make a line through a blocked cell
+                Pieces::Blocked => return 0,
+                Pieces::Player(_) => opp_count += 1,
             }
         }
 
-        // Score Vertical
-        for c in 0..self.cols {
-            let col_array = self.board.slice(s![c..;self.cols]);
-            for r in 0..(self.rows - x_m1) {
-                let window = col_array.slice(s![r..r + self.x_to_win]);
-                score += evaluate_window(&window, cur_piece, self.x_to_win);
+        if opp_count == 0 {
+            if piece_count == x {
+                score += 100;
+            } else if piece_count >= x / 2 {
+                score += 5;
+            } else if piece_count > 1 {
+                score += 2;
             }
+        } else if piece_count == 0 && opp_count >= (0.75 * x as f32) as usize {
+            score -= 4;
+        }
+
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sizes that keep each layer in a u64, a u128 and a list of words
+    const SIZES: [(usize, usize); 3] = [(6, 7), (8, 9), (12, 12)];
+
+    /// A board with `cells` given to player one, which may float
+    fn board_with(rows: usize, cols: usize, x_to_win: usize, cells: &[(usize, usize)]) -> Board {
+        let mut board = Board::new(rows, cols);
+        board.verify(rows, cols, x_to_win);
+        for &(row, col) in cells {
+            board.set_piece(row, col, Pieces::Player(Turn::Player1));
         }
+        board
+    }
+
+    /// A small xorshift generator, so the boards tried are the same every run
+    fn next_random(state: &mut u64) -> usize {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state as usize
+    }
 
-        // Score Diagonals
-        // Negative Diagonal
-        for r in 0..(self.rows - x_m1) {
-            for c in 0..(self.cols - x_m1) {
-                let ip = r * self.cols + c;
-                let fp = (r + x_m1) * self.cols + (c + x_m1);
-                let ss = self.cols + 1;
-                let window = self.board.slice(s![ip..=fp;ss]);
+    /// Everything about the position that a move and its take back must
+    /// leave as it was
+    fn snapshot(board: &Board) -> (Vec<Pieces>, u64, GameState, Vec<usize>) {
+        let cells = (0..board.rows)
+            .flat_map(|row| (0..board.cols).map(move |col| (row, col)))
+            .map(|(row, col)| board.piece_at(row, col))
+            .collect();
+        (cells, board.hash, board.state, board.heights.clone())
+    }
 
-                score += evaluate_window(&window, cur_piece, self.x_to_win);
+    #[test]
+    fn lines_do_not_run_across_the_guard_column() {
+        for (rows, cols) in SIZES {
+            // The end of one row and the start of the next are neighbouring
+            // bits apart from the guard between them
+            for row in 0..rows - 1 {
+                let board = board_with(
+                    rows,
+                    cols,
+                    4,
+                    &[(row, cols - 2), (row, cols - 1), (row + 1, 0), (row + 1, 1)],
+                );
+                assert!(!board.has_line(&board.layers[0]), "{rows}x{cols} row {row}");
+                assert_eq!(board.game_state(), GameState::OnGoing);
             }
         }
+    }
 
-        // Positive Diagonal
-        for r in 0..(self.rows - x_m1) {
-            for c in 0..(self.cols - x_m1) {
-                let ip = r * self.cols + (c + x_m1);
-                let fp = (r + x_m1) * self.cols + c;
-                let ss = self.cols - 1;
-                let window = self.board.slice(s![ip..=fp;ss]);
+    #[test]
+    fn lines_next_to_the_guard_column_win() {
+        for (rows, cols) in SIZES {
+            let last = cols - 1;
+            let lines = [
+                vec![(0, last - 3), (0, last - 2), (0, last - 1), (0, last)],
+                vec![
+                    (rows - 4, last),
+                    (rows - 3, last),
+                    (rows - 2, last),
+                    (rows - 1, last),
+                ],
+                vec![(0, last - 3), (1, last - 2), (2, last - 1), (3, last)],
+                vec![(0, last), (1, last - 1), (2, last - 2), (3, last - 3)],
+            ];
+            for line in lines {
+                let board = board_with(rows, cols, 4, &line);
+                assert!(board.has_line(&board.layers[0]), "{rows}x{cols} {line:?}");
+                assert_eq!(board.scan_state(), GameState::Win(Turn::Player1));
+            }
+        }
+    }
 
-                score += evaluate_window(&window, cur_piece, self.x_to_win);
+    #[test]
+    fn shifted_lines_agree_with_checking_every_piece() {
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        for (rows, cols) in SIZES {
+            for x_to_win in 2..=5 {
+                for _ in 0..200 {
+                    let cells: Vec<(usize, usize)> = (0..rows * cols / 3)
+                        .map(|_| {
+                            let i = next_random(&mut state) % (rows * cols);
+                            (i / cols, i % cols)
+                        })
+                        .collect();
+                    let board = board_with(rows, cols, x_to_win, &cells);
+                    let expected = cells.iter().any(|&(r, c)| board.check_win_at(r, c));
+                    assert_eq!(
+                        board.has_line(&board.layers[0]),
+                        expected,
+                        "{rows}x{cols}x{x_to_win} {cells:?}"
+                    );
+                }
             }
         }
+    }
 
-        score
+    #[test]
+    fn taking_back_moves_restores_the_board_and_hash() {
+        let mut state = 0x9E37_79B9_7F4A_7C15;
+        for (rows, cols) in SIZES {
+            for gravity in [true, false] {
+                let mut board = Board::new(rows, cols);
+                board.verify(rows, cols, 4);
+                board.set_rules(Rules {
+                    gravity,
+                    ..Rules::default()
+                });
+
+                let mut turn = Turn::Player1;
+                let mut played = vec![];
+                while board.game_state() == GameState::OnGoing {
+                    let moves = board.moves(&turn);
+                    let action = moves[next_random(&mut state) % moves.len()];
+                    played.push((snapshot(&board), action, turn));
+                    assert!(board.play(action, &turn));
+                    turn = turn.next(board.players());
+                }
+
+                // The hash depends only on the position reached
+                let (copy, _) = Board::from_notation_with(&board.to_notation(&turn), board.rules)
+                    .expect("the position can be read back");
+                assert_eq!(copy.hash, board.hash);
+
+                while let Some((before, action, turn)) = played.pop() {
+                    board.take_back(action, &turn);
+                    assert_eq!(snapshot(&board), before, "taking back {action:?}");
+                }
+                assert_eq!(board.hash, 0);
+            }
+        }
     }
-}
 
-/// Code modified from https://github.com/KeithGalli/Connect4-Python
-fn evaluate_window(window: &ArrayView1<Pieces>, piece: Pieces, x: usize) -> i32 {
-    let mut score = 0;
+    #[test]
+    fn taking_back_a_winning_move_reopens_the_game() {
+        let mut board = Board::new(6, 7);
+        board.verify(6, 7, 4);
+        for col in [0, 0, 1, 1, 2, 2] {
+            let turn = Turn::from_index(board.piece_count() % 2);
+            board.place(col, &turn);
+        }
+        let before = snapshot(&board);
 
-    let opp_piece = match piece {
-        Pieces::P1 => Pieces::P2,
-        Pieces::P2 => Pieces::P1,
-        Pieces::Empty => Pieces::Empty,
-    };
+        assert!(board.place(3, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        assert!(board.undo(3));
+        assert_eq!(snapshot(&board), before);
+    }
+
+    /// The board for `position` under the PopOut rules
+    fn popout_board(position: &str) -> Board {
+        let rules = Rules {
+            popout: true,
+            ..Rules::default()
+        };
+        Board::from_notation_with(position, rules).unwrap().0
+    }
+
+    /// Pops `col` for `turn`, checking that unpopping gets back to exactly
+    /// the same board, and returns the result of the pop
+    fn pop_and_unpop(position: &str, col: usize, turn: Turn) -> (GameState, Vec<WinningLine>) {
+        let mut board = popout_board(position);
+        let before = snapshot(&board);
+
+        assert!(board.pop(col, &turn));
+        let result = (board.game_state(), board.winning_lines());
+        assert_ne!(snapshot(&board), before);
+
+        board.unpop(col, &turn);
+        assert_eq!(snapshot(&board), before);
+        result
+    }
+
+    #[test]
+    fn popping_can_win() {
+        let (state, lines) = pop_and_unpop("6x7x4 7/7/7/r6/yrrr3/ryry3 r", 0, Turn::Player1);
+        assert_eq!(state, GameState::Win(Turn::Player1));
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].start, (4, 0));
+    }
+
+    #[test]
+    fn popping_a_line_for_everyone_wins_for_the_popper() {
+        let (state, lines) = pop_and_unpop("6x7x4 7/7/7/r6/yrrr3/ryyy3 r", 0, Turn::Player1);
+        assert_eq!(state, GameState::Win(Turn::Player1));
+        let mut winners: Vec<Pieces> = lines.iter().map(|line| line.piece).collect();
+        winners.dedup();
+        assert_eq!(
+            winners,
+            [Pieces::Player(Turn::Player1), Pieces::Player(Turn::Player2)]
+        );
+    }
+
+    #[test]
+    fn popping_a_line_only_for_someone_else_loses() {
+        let (state, _) = pop_and_unpop("6x7x4 7/7/7/r6/yryr3/ryyy3 r", 0, Turn::Player1);
+        assert_eq!(state, GameState::Win(Turn::Player2));
+    }
+
+    #[test]
+    fn popping_without_a_line_carries_on() {
+        let (state, lines) = pop_and_unpop("6x7x4 7/7/7/7/y6/ry5 r", 0, Turn::Player1);
+        assert_eq!(state, GameState::OnGoing);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn only_your_own_bottom_piece_can_be_popped() {
+        let mut board = popout_board("6x7x4 7/7/7/7/r6/ry5 y");
+        assert!(!board.can_pop(0, &Turn::Player2));
+        assert!(!board.pop(0, &Turn::Player2));
+        assert!(!board.can_pop(2, &Turn::Player2));
+        assert!(board.can_pop(1, &Turn::Player2));
+
+        // Nor without the PopOut rules
+        let (board, _) = Board::from_notation("6x7x4 7/7/7/7/r6/ry5 y").unwrap();
+        assert!(!board.can_pop(1, &Turn::Player2));
+    }
+
+    /// The cells of every winning line in `position`
+    fn line_cells(position: &str) -> Vec<Vec<(usize, usize)>> {
+        let (board, _) = Board::from_notation(position).unwrap();
+        board
+            .winning_lines()
+            .iter()
+            .map(|line| line.cells().collect())
+            .collect()
+    }
+
+    #[test]
+    fn no_line_means_no_winning_lines() {
+        assert!(line_cells("6x7x4 7/7/7/7/4y2/rrr1y2 y").is_empty());
+    }
+
+    #[test]
+    fn winning_lines_report_their_cells() {
+        assert_eq!(
+            line_cells("6x7x4 7/7/7/7/yyy4/rrrr3 y"),
+            [[(5, 0), (5, 1), (5, 2), (5, 3)]]
+        );
+        assert_eq!(
+            line_cells("6x7x4 7/7/r6/r6/ry5/ryyy3 y"),
+            [[(2, 0), (3, 0), (4, 0), (5, 0)]]
+        );
+        // Down to the left, starting from the top
+        assert_eq!(
+            line_cells("6x7x4 7/7/3r3/2ry3/1ryy3/ryyr3 y"),
+            [[(2, 3), (3, 2), (4, 1), (5, 0)]]
+        );
+
+        let (board, _) = Board::from_notation("6x7x4 7/7/3r3/2ry3/1ryy3/ryyr3 y").unwrap();
+        let line = &board.winning_lines()[0];
+        assert_eq!(line.piece, Pieces::Player(Turn::Player1));
+        assert_eq!(line.direction, (1, -1));
+        assert_eq!(line.end(), (5, 0));
+    }
 
-    let piece_count = window.iter().filter(|&p| *p == piece).count();
-    let opp_count = window.iter().filter(|&p| *p == opp_piece).count();
+    #[test]
+    fn a_long_line_is_reported_once_at_full_length() {
+        let (board, _) = Board::from_notation("6x7x4 7/7/7/7/yyyy3/rrrrr2 y").unwrap();
+        let lines = board.winning_lines();
+        let lengths: Vec<(Pieces, usize)> =
+            lines.iter().map(|line| (line.piece, line.len)).collect();
+        assert_eq!(
+            lengths,
+            [
+                (Pieces::Player(Turn::Player2), 4),
+                (Pieces::Player(Turn::Player1), 5)
+            ]
+        );
+        assert_eq!(lines[1].start, (5, 0));
+        assert_eq!(lines[1].end(), (5, 4));
+    }
+
+    #[test]
+    fn one_move_can_complete_two_lines() {
+        let mut board = Board::from_notation("6x7x4 7/7/6r/5ry/4ryy/rrr1yyy r")
+            .unwrap()
+            .0;
+        assert!(board.place(3, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        let starts: Vec<(usize, usize)> = board
+            .winning_lines()
+            .iter()
+            .map(|line| line.start)
+            .collect();
+        assert_eq!(starts, [(2, 6), (5, 0)]);
+    }
+
+    #[test]
+    fn cached_results_agree_with_scanning_the_board() {
+        let mut state = 0xBF58_476D_1CE4_E5B9;
+        let variants = [
+            Rules::default(),
+            Rules {
+                gravity: false,
+                ..Rules::default()
+            },
+            Rules {
+                wrap_horizontal: true,
+                wrap_vertical: true,
+                ..Rules::default()
+            },
+        ];
+        for (rows, cols) in SIZES {
+            for rules in variants {
+                for x_to_win in 3..=5 {
+                    let mut board = Board::new(rows, cols);
+                    board.verify(rows, cols, x_to_win);
+                    board.set_rules(rules);
 
-    if opp_count == 0 {
-        if piece_count == x {
-            score += 100;
-        } else if piece_count >= x / 2 {
-            score += 5;
-        } else if piece_count > 1 {
-            score += 2;
+                    let mut turn = Turn::Player1;
+                    let mut played = vec![];
+                    while board.game_state() == GameState::OnGoing {
+                        let moves = board.moves(&turn);
+                        let action = moves[next_random(&mut state) % moves.len()];
+                        board.play(action, &turn);
+                        assert_eq!(board.game_state(), board.scan_state(), "{action:?}");
+                        played.push((action, turn));
+                        turn = turn.next(board.players());
+                    }
+
+                    while let Some((action, turn)) = played.pop() {
+                        board.take_back(action, &turn);
+                        assert_eq!(board.game_state(), board.scan_state(), "{action:?}");
+                    }
+                }
+            }
         }
-    } else if piece_count == 0 && opp_count >= (0.75 * x as f32) as usize {
-        score -= 4;
     }
 
-    score
+    #[test]
+    fn filling_a_gap_wins() {
+        let mut board = Board::from_notation("6x7x4 7/7/7/7/yy1y3/rr1r3 r")
+            .unwrap()
+            .0;
+        assert!(!board.check_win_at(5, 0));
+        assert!(board.place(2, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        assert!(board.check_win_at(5, 2));
+        assert!(board.check_win_at(5, 0));
+        assert!(!board.check_win_at(4, 0));
+        assert!(!board.check_win_at(0, 0));
+    }
+
+    #[test]
+    fn filling_the_last_cell_ties() {
+        let mut board = Board::from_notation("3x3x3 1yr/ryr/yry r").unwrap().0;
+        assert_eq!(board.game_state(), GameState::OnGoing);
+        assert!(board.place(0, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Tie);
+        assert!(board.undo(0));
+        assert_eq!(board.game_state(), GameState::OnGoing);
+    }
+
+    #[test]
+    fn taking_back_a_later_move_keeps_the_win() {
+        let mut board = Board::from_notation("6x7x4 7/7/7/7/yyy4/rrrr3 y")
+            .unwrap()
+            .0;
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        board.place(6, &Turn::Player2);
+        assert!(board.undo(6));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        assert!(board.undo(3));
+        assert_eq!(board.game_state(), GameState::OnGoing);
+    }
+
+    /// The board for `position` without gravity
+    fn gravity_free_board(position: &str) -> Board {
+        let rules = Rules {
+            gravity: false,
+            ..Rules::default()
+        };
+        Board::from_notation_with(position, rules).unwrap().0
+    }
+
+    #[test]
+    fn without_gravity_every_empty_cell_can_be_played() {
+        let board = gravity_free_board("3x3x3 r2/1#1/2y r");
+        let moves = board.moves(&Turn::Player1);
+        assert_eq!(
+            moves,
+            [
+                Action::Put(0, 1),
+                Action::Put(0, 2),
+                Action::Put(1, 0),
+                Action::Put(1, 2),
+                Action::Put(2, 0),
+                Action::Put(2, 1),
+            ]
+        );
+        assert!(board.floating_piece().is_some());
+    }
+
+    #[test]
+    fn without_gravity_pieces_stay_where_they_are_put() {
+        let mut board = gravity_free_board("3x3x3 3/3/3 r");
+        assert!(!board.place(1, &Turn::Player1));
+        assert!(!board.play(Action::Drop(1), &Turn::Player1));
+
+        assert!(board.put_piece(0, 1, &Turn::Player1));
+        assert_eq!(board.piece_at(0, 1), Pieces::Player(Turn::Player1));
+        assert_eq!(board.piece_at(2, 1), Pieces::Empty);
+
+        // Taken and off the board cells can't be played
+        assert!(!board.put_piece(0, 1, &Turn::Player2));
+        assert!(!board.put_piece(3, 0, &Turn::Player2));
+        assert!(!board.put_piece(0, 3, &Turn::Player2));
+        assert_eq!(board.piece_count(), 1);
+
+        // Nor can cells be picked with gravity
+        let (mut board, _) = Board::from_notation("3x3x3 3/3/3 r").unwrap();
+        assert!(!board.put_piece(2, 1, &Turn::Player1));
+    }
+
+    #[test]
+    fn without_gravity_lines_can_be_made_anywhere() {
+        let mut board = gravity_free_board("4x4x3 r3/4/2r1/4 r");
+        assert_eq!(board.game_state(), GameState::OnGoing);
+        assert!(board.play(Action::Put(1, 1), &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        assert_eq!(board.winning_lines()[0].start, (0, 0));
+
+        board.take_back(Action::Put(1, 1), &Turn::Player1);
+        assert_eq!(board.game_state(), GameState::OnGoing);
+        assert_eq!(board.piece_at(1, 1), Pieces::Empty);
+    }
+
+    #[test]
+    fn without_gravity_the_board_fills_up_to_a_tie() {
+        // The last empty cell is in the middle, not at the top
+        let mut board = gravity_free_board("3x3x3 ryr/r1r/yry y");
+        assert_eq!(board.game_state(), GameState::OnGoing);
+        assert_eq!(board.moves(&Turn::Player2), [Action::Put(1, 1)]);
+        assert!(board.put_piece(1, 1, &Turn::Player2));
+        assert_eq!(board.game_state(), GameState::Tie);
+        assert!(board.moves(&Turn::Player1).is_empty());
+    }
+
+    /// The board for `position` with lines wrapping as given
+    fn wrapping_board(position: &str, wrap_horizontal: bool, wrap_vertical: bool) -> Board {
+        let rules = Rules {
+            wrap_horizontal,
+            wrap_vertical,
+            ..Rules::default()
+        };
+        Board::from_notation_with(position, rules).unwrap().0
+    }
+
+    #[test]
+    fn lines_wrap_from_the_last_column_into_the_first() {
+        let position = "6x7x4 7/7/7/7/yy4y/rr4r r";
+        let mut board = wrapping_board(position, true, false);
+        assert!(board.place(5, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        let lines = board.winning_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            lines[0].cells().collect::<Vec<_>>(),
+            [(5, 5), (5, 6), (5, 0), (5, 1)]
+        );
+
+        // The same move doesn't win without wrapping
+        let mut board = Board::from_notation(position).unwrap().0;
+        assert!(board.place(5, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::OnGoing);
+    }
+
+    #[test]
+    fn diagonals_wrap_around_the_sides() {
+        let rules = Rules {
+            gravity: false,
+            wrap_horizontal: true,
+            ..Rules::default()
+        };
+        let position = "6x7x4 yyy4/7/5r1/6r/r6/7 r";
+        let mut board = Board::from_notation_with(position, rules).unwrap().0;
+        assert!(board.put_piece(5, 1, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        let lines = board.winning_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].direction, (1, 1));
+        assert_eq!(
+            lines[0].cells().collect::<Vec<_>>(),
+            [(2, 5), (3, 6), (4, 0), (5, 1)]
+        );
+        assert_eq!(lines[0].end(), (5, 1));
+    }
+
+    #[test]
+    fn a_line_all_the_way_round_is_counted_once() {
+        // A full row on a board exactly x wide loops back to where it started
+        let board = wrapping_board("4x4x4 4/4/yyy1/rrrr y", true, true);
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        let lines = board.winning_lines();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert_eq!(lines[0].len, 4);
+        assert_eq!(lines[0].start, (3, 0));
+
+        // And so does a full column on a board exactly x high
+        let board = wrapping_board("4x4x4 r3/ry2/ry2/ry2 r", false, true);
+        let lines = board.winning_lines();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert_eq!(lines[0].direction, (1, 0));
+        assert_eq!(lines[0].cells().count(), 4);
+    }
+
+    #[test]
+    fn vertical_wrapping_joins_the_bottom_row_to_the_top() {
+        let rules = Rules {
+            gravity: false,
+            wrap_vertical: true,
+            ..Rules::default()
+        };
+        let mut board = Board::from_notation_with("5x5x3 r4/5/5/4y/r3y r", rules)
+            .unwrap()
+            .0;
+        assert!(board.put_piece(3, 0, &Turn::Player1));
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+        assert_eq!(
+            board.winning_lines()[0].cells().collect::<Vec<_>>(),
+            [(3, 0), (4, 0), (0, 0)]
+        );
+    }
 }
@@ -0,0 +1,395 @@
+//! Command line settings shared by the front ends.
+//!
+//! Every setting is a `--name value` pair (or `--name=value`), apart from the
+//! `--popout`, `--no-gravity`, `--wrap`, `--wrap-vertical`, `--autostart` and
+//! `--help` flags. Agents are given by name, optionally followed by a think
+//! time in seconds, such as `alphabeta:2.5` or `mcts:0.5`, and for the
+//! searching agents a depth limit, such as `alphabeta:10:4`. An engine program
+//! is `external:` followed by the command to run it.
+//!
+//! On the web the same settings come from the page's query string, so
+//! `?rows=8&p1=mcts&autostart` is read like `--rows=8 --p1=mcts --autostart`.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::{
+    agent::{Agent, SearchLimits},
+    board::Rules,
+    external::ExternalEngine,
+    mcts::MctsLimit,
+    shape::Shape,
+    MAX_COL, MAX_PLAYERS, MAX_ROW, MIN_PLAYERS,
+};
+
+pub const USAGE: &str = "\
+Options:
+  --rows <N>        Rows on the board [default: 6]
+  --cols <N>        Columns on the board [default: 7]
+  --x <N>           Pieces in a row needed to win [default: 4]
+  --players <N>     Players taking turns, from 2 to 8 [default: 2]
+  --p1 <AGENT>      Agent for player 1 [default: player]
+  --p2 <AGENT>      Agent for player 2, and so on up to --p8 [default: player]
+  --popout          Play by the PopOut rules, where pieces can be popped off
+                    the bottom
+  --no-gravity      Let pieces go in any empty cell instead of falling
+  --wrap            Let lines carry on from the last column into the first
+  --wrap-vertical   Let lines carry on from the bottom row into the top
+  --shape <SHAPE>   Cells to block off: plain, holes, pyramid or staggered
+                    [default: plain]
+  --sleep <SECS>    Pause between moves [default: 0]
+  --autostart       Start the game without waiting for the Start button
+  --help            Print this message
+
+Agents: player, random, alphabeta, mcts or solver, optionally with a think
+time in seconds such as alphabeta:2.5. Alphabeta and solver also take a
+depth limit after the think time, such as alphabeta:10:4. An engine program
+is given as external:<command>";
+
+/// The options that are switched on by being given, without a value
+const FLAGS: [&str; 5] = ["autostart", "popout", "no-gravity", "wrap", "wrap-vertical"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    /// `--help` was given, so the caller should print [`USAGE`] and stop
+    Help,
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+    },
+    /// `--x` is larger than the board
+    InvalidX {
+        x_val: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{USAGE}"),
+            CliError::UnknownOption(option) => write!(f, "unknown option '{option}'"),
+            CliError::MissingValue(option) => write!(f, "'{option}' needs a value"),
+            CliError::InvalidValue { option, value } => {
+                write!(f, "'{value}' is not a valid value for '{option}'")
+            }
+            CliError::InvalidX { x_val, max } => write!(
+                f,
+                "--x is {x_val} but can be at most {max}, the smaller side of the board"
+            ),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+/// The settings a game is started with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub rows: usize,
+    pub cols: usize,
+    pub x_val: usize,
+    pub players: usize,
+    /// The agent for each player in turn order, one for every possible player
+    /// so that changing `players` keeps the choices made
+    pub agents: Vec<Agent>,
+    pub rules: Rules,
+    pub shape: Shape,
+    pub sleep_time: f64,
+    pub autostart: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            rows: 6,
+            cols: 7,
+            x_val: 4,
+            players: 2,
+            agents: vec![Agent::Player; MAX_PLAYERS],
+            rules: Rules::default(),
+            shape: Shape::Plain,
+            sleep_time: 0.0,
+            autostart: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads settings from command line arguments, not including the program
+    /// name. A single leading dash is accepted too, which is how the web build
+    /// passes one letter query parameters such as `x`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, CliError> {
+        let mut settings = Settings::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-')) else {
+                return Err(CliError::UnknownOption(arg));
+            };
+            if option == "help" {
+                return Err(CliError::Help);
+            }
+
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name.to_owned(), value.to_owned()),
+                None if FLAGS.contains(&option) => (option.to_owned(), "true".to_owned()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                    (option.to_owned(), value)
+                }
+            };
+            settings.set(&name, &value)?;
+        }
+        settings.check()?;
+        Ok(settings)
+    }
+
+    /// Changes the setting called `name`, which is an option without its
+    /// leading dashes.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), CliError> {
+        let invalid = || CliError::InvalidValue {
+            option: format!("--{name}"),
+            value: value.to_owned(),
+        };
+
+        match name {
+            "rows" => self.rows = parse_in(value, 1..=MAX_ROW).ok_or_else(invalid)?,
+            "cols" => self.cols = parse_in(value, 1..=MAX_COL).ok_or_else(invalid)?,
+            "x" => self.x_val = parse_in(value, 1..=MAX_ROW.max(MAX_COL)).ok_or_else(invalid)?,
+            "players" => {
+                self.players = parse_in(value, MIN_PLAYERS..=MAX_PLAYERS).ok_or_else(invalid)?
+            }
+            "sleep" => {
+                self.sleep_time = value
+                    .parse()
+                    .ok()
+                    .filter(|secs: &f64| secs.is_finite() && *secs >= 0.0)
+                    .ok_or_else(invalid)?
+            }
+            "popout" => self.rules.popout = value.parse().map_err(|_| invalid())?,
+            "no-gravity" => self.rules.gravity = !value.parse::<bool>().map_err(|_| invalid())?,
+            "shape" => self.shape = value.parse().map_err(|_| invalid())?,
+            "wrap" => self.rules.wrap_horizontal = value.parse().map_err(|_| invalid())?,
+            "wrap-vertical" => self.rules.wrap_vertical = value.parse().map_err(|_| invalid())?,
+            "autostart" => self.autostart = value.parse().map_err(|_| invalid())?,
+            _ => match name
+                .strip_prefix('p')
+                .and_then(|n| parse_in(n, 1..=MAX_PLAYERS))
+            {
+                Some(player) => self.agents[player - 1] = value.parse().map_err(|_| invalid())?,
+                None => return Err(CliError::UnknownOption(format!("--{name}"))),
+            },
+        }
+        Ok(())
+    }
+
+    /// Checks the settings that depend on each other.
+    pub fn check(&self) -> Result<(), CliError> {
+        let max = self.rows.min(self.cols);
+        if self.x_val > max {
+            return Err(CliError::InvalidX {
+                x_val: self.x_val,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn parse_in(value: &str, range: std::ops::RangeInclusive<usize>) -> Option<usize> {
+    value.parse().ok().filter(|n| range.contains(n))
+}
+
+impl FromStr for Agent {
+    type Err = ();
+
+    /// Reads an agent name, case insensitively, with an optional think time
+    /// and for the searching agents an optional depth limit after that.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        // Everything after the name is the command, which may contain colons
+        if let Some(command) = text.strip_prefix("external:") {
+            return Ok(Agent::External(ExternalEngine {
+                command: command.to_owned(),
+                ..ExternalEngine::default()
+            }));
+        }
+
+        let mut fields = text.split(':');
+        let name = fields.next().unwrap_or_default().to_ascii_lowercase();
+        let think_time = match fields.next() {
+            Some(secs) => {
+                let secs: f64 = secs.parse().map_err(|_| ())?;
+                if !(secs.is_finite() && secs > 0.0) {
+                    return Err(());
+                }
+                Some(secs)
+            }
+            None => None,
+        };
+        let max_depth = match fields.next() {
+            Some(depth) => Some(depth.parse::<u32>().ok().filter(|&d| d > 0).ok_or(())?),
+            None => None,
+        };
+        if fields.next().is_some() {
+            return Err(());
+        }
+
+        let limits = SearchLimits {
+            think_time: think_time.unwrap_or(SearchLimits::default().think_time),
+            max_depth: max_depth.unwrap_or(SearchLimits::default().max_depth),
+        };
+        match (name.as_str(), think_time, max_depth) {
+            ("player", None, None) => Ok(Agent::Player),
+            ("random", None, None) => Ok(Agent::Random),
+            ("alphabeta", _, _) => Ok(Agent::AlphaBeta(limits)),
+            ("solver", _, _) => Ok(Agent::Solver(limits)),
+            ("mcts", Some(secs), None) => Ok(Agent::Mcts(MctsLimit::Time(secs))),
+            ("mcts", None, None) => Ok(Agent::Mcts(MctsLimit::default())),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Settings, CliError> {
+        Settings::from_args(args.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn values_can_follow_an_equals_sign_or_a_space() {
+        let settings = parse("--x=5 --rows 8 --cols=9").unwrap();
+        assert_eq!((settings.rows, settings.cols, settings.x_val), (8, 9, 5));
+        assert_eq!(parse("--x 5").unwrap().x_val, 5);
+        assert_eq!(parse("--sleep 0.5").unwrap().sleep_time, 0.5);
+    }
+
+    #[test]
+    fn web_query_parameters_have_a_single_dash() {
+        let settings = parse("-x=3 -rows=5 -p1=mcts -autostart").unwrap();
+        assert_eq!((settings.rows, settings.x_val), (5, 3));
+        assert_eq!(settings.agents[0], Agent::Mcts(MctsLimit::default()));
+        assert!(settings.autostart);
+    }
+
+    #[test]
+    fn flags_need_no_value() {
+        let settings = parse("--autostart --popout --wrap").unwrap();
+        assert!(settings.autostart);
+        assert!(settings.rules.popout);
+        assert!(settings.rules.wrap_horizontal);
+        assert!(!settings.rules.wrap_vertical);
+        assert!(!parse("--no-gravity").unwrap().rules.gravity);
+
+        assert_eq!(parse("").unwrap(), Settings::default());
+        assert!(!parse("--autostart=false").unwrap().autostart);
+    }
+
+    #[test]
+    fn x_has_to_fit_on_the_board() {
+        assert_eq!(
+            parse("--rows 4 --cols 9 --x 5"),
+            Err(CliError::InvalidX { x_val: 5, max: 4 })
+        );
+        assert_eq!(parse("--x 8"), Err(CliError::InvalidX { x_val: 8, max: 6 }));
+        assert!(parse("--rows 4 --cols 9 --x 4").is_ok());
+    }
+
+    #[test]
+    fn bad_options_are_reported() {
+        assert_eq!(
+            parse("--colour red"),
+            Err(CliError::UnknownOption("--colour".to_owned()))
+        );
+        assert_eq!(
+            parse("rows"),
+            Err(CliError::UnknownOption("rows".to_owned()))
+        );
+        assert_eq!(
+            parse("--p9 random"),
+            Err(CliError::UnknownOption("--p9".to_owned()))
+        );
+        assert_eq!(
+            parse("--rows"),
+            Err(CliError::MissingValue("--rows".to_owned()))
+        );
+        assert_eq!(
+            parse("--rows 0"),
+            Err(CliError::InvalidValue {
+                option: "--rows".to_owned(),
+                value: "0".to_owned()
+            })
+        );
+        assert!(matches!(
+            parse("--players 9"),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse("--sleep -1"),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert_eq!(parse("--rows 5 --help"), Err(CliError::Help));
+    }
+
+    #[test]
+    fn agents_are_read_with_their_limits() {
+        assert_eq!("Player".parse(), Ok(Agent::Player));
+        assert_eq!("random".parse(), Ok(Agent::Random));
+        assert_eq!(
+            "alphabeta:10:4".parse(),
+            Ok(Agent::AlphaBeta(SearchLimits {
+                think_time: 10.0,
+                max_depth: 4
+            }))
+        );
+        assert_eq!(
+            "solver:2.5".parse(),
+            Ok(Agent::Solver(SearchLimits {
+                think_time: 2.5,
+                ..SearchLimits::default()
+            }))
+        );
+        assert_eq!("mcts:0.5".parse(), Ok(Agent::Mcts(MctsLimit::Time(0.5))));
+
+        let settings = parse("--p2 alphabeta:10:4").unwrap();
+        assert_eq!(settings.agents[0], Agent::Player);
+        assert!(matches!(settings.agents[1], Agent::AlphaBeta(_)));
+    }
+
+    #[test]
+    fn external_agents_keep_their_whole_command() {
+        let agent: Agent = "external:./engine --depth=3 -t:2".parse().unwrap();
+        let Agent::External(engine) = agent else {
+            panic!("not an external agent");
+        };
+        assert_eq!(engine.command, "./engine --depth=3 -t:2");
+    }
+
+    #[test]
+    fn bad_agents_are_rejected() {
+        for text in [
+            "",
+            "human",
+            "random:1",
+            "player:2",
+            "mcts:1:4",
+            "alphabeta:0",
+            "alphabeta:-1",
+            "alphabeta:inf",
+            "alphabeta:1:0",
+            "alphabeta:1:2:3",
+            "solver:x",
+        ] {
+            assert_eq!(text.parse::<Agent>(), Err(()), "{text}");
+        }
+        assert!(matches!(
+            parse("--p1 human"),
+            Err(CliError::InvalidValue { .. })
+        ));
+    }
+}
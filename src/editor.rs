@@ -0,0 +1,147 @@
+//! Setting up a position by hand, to analyse it or play on from it.
+
+use std::{error::Error, fmt};
+
+use crate::{
+    board::{Board, Pieces},
+    Turn,
+};
+
+/// Why a position couldn't have come up in a game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    /// A piece has an empty cell below it, or sits under a blocked cell that
+    /// it couldn't have fallen past
+    FloatingPiece { row: usize, col: usize },
+    /// The players don't have the number of pieces that taking turns from an
+    /// empty board gives
+    UnevenPieces,
+    /// The pieces fit with `expected` to move rather than the side chosen
+    WrongTurn { expected: Turn },
+    /// More than one player has a line
+    SeveralWinners,
+    /// `winner` has a line but wasn't the last to move
+    WrongWinner { winner: Turn },
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::FloatingPiece { row, col } => write!(
+                f,
+                "the piece in row {}, column {} couldn't have fallen there",
+                row + 1,
+                col + 1
+            ),
+            PositionError::UnevenPieces => {
+                write!(f, "the players' piece counts don't come from taking turns")
+            }
+            PositionError::WrongTurn { expected } => {
+                write!(f, "the piece counts have {expected} to move")
+            }
+            PositionError::SeveralWinners => write!(f, "more than one player has a line"),
+            PositionError::WrongWinner { winner } => {
+                write!(f, "{winner} has a line but didn't move last")
+            }
+        }
+    }
+}
+
+impl Error for PositionError {}
+
+/// Changes the cell at `(row, col)` to the next of empty, each player's piece
+/// and blocked. With gravity the pieces in the column then fall as far as
+/// they can.
+pub fn cycle_cell(board: &mut Board, row: usize, col: usize) {
+    let players = board.players();
+    let next = match board.piece_at(row, col) {
+        Pieces::Empty => Pieces::Player(Turn::Player1),
+        Pieces::Player(turn) if turn.index() + 1 < players => Pieces::Player(turn.next(players)),
+        Pieces::Player(_) => Pieces::Blocked,
+        Pieces::Blocked => Pieces::Empty,
+    };
+    board.set_piece(row, col, next);
+    if board.rules().gravity {
+        settle(board, col);
+    }
+}
+
+/// Lets every piece in `col` fall until it rests on a piece, a blocked cell or
+/// the bottom of the board.
+fn settle(board: &mut Board, col: usize) {
+    // From the bottom up, so each piece lands on the ones already settled
+    for row in (0..board.rows()).rev() {
+        let piece = board.piece_at(row, col);
+        if !matches!(piece, Pieces::Player(_)) {
+            continue;
+        }
+        let mut to = row;
+        while to + 1 < board.rows() && board.piece_at(to + 1, col) == Pieces::Empty {
+            to += 1;
+        }
+        if to != row {
+            board.set_piece(row, col, Pieces::Empty);
+            board.set_piece(to, col, piece);
+        }
+    }
+}
+
+/// Checks that the position on `board` with `turn` to move could come up in a
+/// game played by the board's rules.
+///
+/// Pops take pieces away and can complete lines for several players at once,
+/// so under the PopOut rules the piece counts and winners aren't checked.
+pub fn check_position(board: &Board, turn: &Turn) -> Result<(), PositionError> {
+    let rules = board.rules();
+    let players = board.players();
+
+    if rules.gravity {
+        if let Some((row, col)) = board.floating_piece() {
+            return Err(PositionError::FloatingPiece { row, col });
+        }
+    }
+    if rules.popout {
+        return Ok(());
+    }
+
+    let mut counts = vec![0; players];
+    for row in 0..board.rows() {
+        for col in 0..board.cols() {
+            if let Pieces::Player(player) = board.piece_at(row, col) {
+                counts[player.index()] += 1;
+            }
+        }
+    }
+    // Everyone has had the same number of turns, apart from the players
+    // before the one to move who have had one more
+    let total: usize = counts.iter().sum();
+    let (rounds, extra) = (total / players, total % players);
+    if counts
+        .iter()
+        .enumerate()
+        .any(|(i, &count)| count != rounds + usize::from(i < extra))
+    {
+        return Err(PositionError::UnevenPieces);
+    }
+    let expected = Turn::from_index(extra);
+    if *turn != expected {
+        return Err(PositionError::WrongTurn { expected });
+    }
+
+    let mut winners: Vec<Turn> = board
+        .winning_lines()
+        .iter()
+        .filter_map(|line| match line.piece {
+            Pieces::Player(player) => Some(player),
+            Pieces::Empty | Pieces::Blocked => None,
+        })
+        .collect();
+    winners.sort_by_key(Turn::index);
+    winners.dedup();
+    match winners[..] {
+        [] => Ok(()),
+        [winner] if winner == turn.previous(players) => Ok(()),
+        [winner] => Err(PositionError::WrongWinner { winner }),
+        _ => Err(PositionError::SeveralWinners),
+    }
+}
@@ -0,0 +1,199 @@
+//! Playing through another program that talks the [`protocol`].
+//!
+//! The engine is started afresh for every move and told the position from
+//! scratch, so it needs no memory of earlier moves and a crash only costs a
+//! single move.
+//!
+//! [`protocol`]: crate::protocol
+
+use serde::{Deserialize, Serialize};
+
+/// How long an engine may take on top of its think time, to start up and
+/// answer the handshake
+#[cfg(not(target_arch = "wasm32"))]
+const GRACE_TIME: f64 = 5.0;
+
+/// An engine program and how long it is given for each move
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalEngine {
+    /// The program to run followed by its arguments, separated by spaces
+    pub command: String,
+    /// Seconds to think about each move
+    pub think_time: f64,
+}
+
+impl Default for ExternalEngine {
+    fn default() -> Self {
+        ExternalEngine {
+            command: String::new(),
+            think_time: 1.0,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use session::Session;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod session {
+    use std::{
+        io::{self, BufRead, BufReader, Write},
+        process::{Child, ChildStdin, Command as Process, Stdio},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::{self, Receiver, RecvTimeoutError},
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::{ExternalEngine, GRACE_TIME};
+    use crate::{
+        agent::Clock,
+        board::{Action, Board},
+        notation::format_action,
+        protocol::{Command, Response},
+        Turn,
+    };
+
+    /// Longest wait for a line before checking the clock and cancel flag again
+    const POLL_INTERVAL: f64 = 0.05;
+
+    /// A running engine being asked for one move.
+    pub(crate) struct Session {
+        child: Child,
+        stdin: ChildStdin,
+        /// Lines from the engine, read on their own thread so waiting for them
+        /// can time out
+        lines: Receiver<String>,
+        board: Board,
+        turn: Turn,
+        think_time: f64,
+        handshake_done: bool,
+        deadline: f64,
+    }
+
+    impl Session {
+        /// Starts the engine and begins the handshake.
+        pub fn start(
+            engine: &ExternalEngine,
+            board: &Board,
+            turn: &Turn,
+            clock: Clock,
+        ) -> io::Result<Self> {
+            let mut words = engine.command.split_whitespace();
+            let program = words
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no command given"))?;
+            let mut child = Process::new(program)
+                .args(words)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::inherit())
+                .spawn()?;
+
+            let stdin = child.stdin.take().unwrap();
+            let stdout = child.stdout.take().unwrap();
+            let (sender, lines) = mpsc::channel();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else { break };
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let mut session = Session {
+                child,
+                stdin,
+                lines,
+                board: board.clone(),
+                turn: *turn,
+                think_time: engine.think_time,
+                handshake_done: false,
+                deadline: clock() + engine.think_time + GRACE_TIME,
+            };
+            session.send(&Command::Cxi)?;
+            Ok(session)
+        }
+
+        fn send(&mut self, command: &Command) -> io::Result<()> {
+            writeln!(self.stdin, "{command}")?;
+            self.stdin.flush()
+        }
+
+        /// Talks to the engine until it answers, the clock reaches `until` or
+        /// `cancel` is set.
+        ///
+        /// Returns `None` while still waiting, otherwise the engine's move or
+        /// why there isn't one.
+        pub fn poll(
+            &mut self,
+            clock: Clock,
+            until: f64,
+            cancel: &AtomicBool,
+        ) -> Option<Result<Action, String>> {
+            loop {
+                if cancel.load(Ordering::Relaxed) {
+                    return Some(Err("cancelled".to_owned()));
+                }
+                let now = clock();
+                if now >= self.deadline {
+                    return Some(Err("no move in time".to_owned()));
+                }
+                if now >= until {
+                    return None;
+                }
+
+                let wait = (until.min(self.deadline) - now).min(POLL_INTERVAL);
+                let line = match self.lines.recv_timeout(Duration::from_secs_f64(wait)) {
+                    Ok(line) => line,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Some(Err("the engine exited".to_owned()))
+                    }
+                };
+
+                match line.parse::<Response>() {
+                    Ok(Response::CxiOk) if !self.handshake_done => {
+                        self.handshake_done = true;
+                        if let Err(err) = self.ask_for_move() {
+                            return Some(Err(err.to_string()));
+                        }
+                    }
+                    Ok(Response::BestMove(action)) if self.handshake_done => {
+                        if !self.board.moves(&self.turn).contains(&action) {
+                            return Some(Err(format!("{} can't be played", format_action(action))));
+                        }
+                        return Some(Ok(action));
+                    }
+                    // Anything else is information or noise
+                    _ => {}
+                }
+            }
+        }
+
+        fn ask_for_move(&mut self) -> io::Result<()> {
+            self.send(&Command::NewGame {
+                rows: self.board.rows(),
+                cols: self.board.cols(),
+                x_to_win: self.board.x_to_win(),
+                players: self.board.players(),
+                rules: self.board.rules(),
+            })?;
+            self.send(&Command::Fen(self.board.to_notation(&self.turn)))?;
+            self.send(&Command::Go {
+                movetime: (self.think_time * 1000.0) as u64,
+            })
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            let _ = self.send(&Command::Quit);
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::{Action, Board, Rules},
+    Turn,
+};
+
+/// `action` made by `turn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Move {
+    pub action: Action,
+    pub turn: Turn,
+}
+
+/// A board together with whose turn it is and the moves that led here.
+///
+/// Undone moves are kept so they can be redone, until a different move is
+/// played.
+#[derive(Debug, Clone)]
+pub struct Game {
+    board: Board,
+    turn: Turn,
+    history: Vec<Move>,
+    undone: Vec<Move>,
+    /// The position the moves were played from and who moved first, or
+    /// `None` for an empty board
+    start: Option<(Board, Turn)>,
+}
+
+impl Game {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Game {
+            board: Board::new(rows, cols),
+            turn: Turn::Player1,
+            history: vec![],
+            undone: vec![],
+            start: None,
+        }
+    }
+
+    /// Starts the game over from `board` with `turn` to move.
+    pub fn set_position(&mut self, board: Board, turn: Turn) {
+        self.start = Some((board.clone(), turn));
+        self.board = board;
+        self.turn = turn;
+        self.history.clear();
+        self.undone.clear();
+    }
+
+    /// The position the game was set up from with [`Game::set_position`] in
+    /// the notation of [`Board::to_notation`], if it didn't start from an
+    /// empty board.
+    pub fn start(&self) -> Option<String> {
+        self.start
+            .as_ref()
+            .map(|(board, turn)| board.to_notation(turn))
+    }
+
+    /// Resizes the board if the settings have changed, which starts a new game.
+    pub fn verify(&mut self, rows: usize, cols: usize, x_to_win: usize) {
+        if self.board.rows() != rows || self.board.cols() != cols {
+            self.reset();
+        }
+        self.board.verify(rows, cols, x_to_win);
+        if let Some((start, _)) = &mut self.start {
+            start.verify(rows, cols, x_to_win);
+        }
+    }
+
+    /// Changes the number of players, which starts a new game if it differs.
+    pub fn set_players(&mut self, players: usize) {
+        if self.board.players() != players {
+            self.reset();
+            self.board.set_players(players);
+        }
+    }
+
+    /// Changes the rules, which starts a new game if they differ.
+    pub fn set_rules(&mut self, rules: Rules) {
+        if self.board.rules() != rules {
+            self.reset();
+            self.board.set_rules(rules);
+        }
+    }
+
+    /// Blocks off exactly the cells in `blocked`, which starts a new game if
+    /// they differ from the board's.
+    pub fn set_shape(&mut self, blocked: &[(usize, usize)]) {
+        let mut blocked = blocked.to_vec();
+        blocked.sort();
+        blocked.dedup();
+        if self.board.blocked_cells() != blocked {
+            self.reset();
+            self.board.set_shape(&blocked);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.board.reset();
+        self.turn = Turn::Player1;
+        self.history.clear();
+        self.undone.clear();
+        self.start = None;
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn turn(&self) -> Turn {
+        self.turn
+    }
+
+    pub fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// How many moves have been played to reach the current position.
+    pub fn ply(&self) -> usize {
+        self.history.len()
+    }
+
+    /// How many moves the game has including the ones that were undone, so
+    /// the last ply that can be reached with [`Game::redo`].
+    pub fn plies(&self) -> usize {
+        self.history.len() + self.undone.len()
+    }
+
+    /// Undoes or redoes moves until `ply` moves have been played, stopping at
+    /// either end of the game.
+    pub fn seek(&mut self, ply: usize) {
+        while self.ply() > ply && self.undo().is_some() {}
+        while self.ply() < ply && self.redo().is_some() {}
+    }
+
+    /// Makes `action` for the player whose turn it is.
+    pub fn play(&mut self, action: Action) -> bool {
+        if !self.board.play(action, &self.turn) {
+            return false;
+        }
+
+        self.history.push(Move {
+            action,
+            turn: self.turn,
+        });
+        self.undone.clear();
+        self.turn = self.turn.next(self.board.players());
+        true
+    }
+
+    /// Takes back the last move, handing the turn back to whoever made it.
+    pub fn undo(&mut self) -> Option<Move> {
+        let last = self.history.pop()?;
+        self.board.take_back(last.action, &last.turn);
+        self.turn = last.turn;
+        self.undone.push(last);
+        Some(last)
+    }
+
+    /// Plays the most recently undone move again.
+    pub fn redo(&mut self) -> Option<Move> {
+        let next = self.undone.pop()?;
+        self.board.play(next.action, &next.turn);
+        self.turn = next.turn.next(self.board.players());
+        self.history.push(next);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 6x7 Connect 4 game after `moves`
+    fn game_after(moves: &str) -> Game {
+        let mut game = Game::new(6, 7);
+        game.verify(6, 7, 4);
+        game.play_notation(moves).unwrap();
+        game
+    }
+
+    /// Everything about where the game has got to
+    fn snapshot(game: &Game) -> (String, Turn, u64) {
+        (
+            game.board().to_notation(&game.turn()),
+            game.turn(),
+            game.board().hash(),
+        )
+    }
+
+    #[test]
+    fn undo_then_redo_comes_back_to_the_same_position() {
+        let mut game = game_after("4433521");
+        let before = snapshot(&game);
+
+        for _ in 0..3 {
+            game.undo().unwrap();
+        }
+        assert_eq!(snapshot(&game), snapshot(&game_after("4433")));
+        assert_eq!(game.turn(), Turn::Player1);
+
+        for _ in 0..3 {
+            game.redo().unwrap();
+        }
+        assert_eq!(snapshot(&game), before);
+        assert_eq!(game.to_move_notation(), "4433521");
+    }
+
+    #[test]
+    fn a_new_move_throws_away_the_undone_ones() {
+        let mut game = game_after("4433");
+        game.undo();
+        game.undo();
+        assert!(game.can_redo());
+        assert_eq!(game.plies(), 4);
+
+        assert!(game.play(Action::Drop(0)));
+        assert!(!game.can_redo());
+        assert_eq!(game.redo(), None);
+        assert_eq!(game.plies(), 3);
+        assert_eq!(game.to_move_notation(), "441");
+    }
+
+    #[test]
+    fn an_illegal_move_keeps_the_undone_ones() {
+        let mut game = game_after("4444445");
+        game.undo();
+        assert!(!game.play(Action::Drop(3)));
+        assert!(game.can_redo());
+        assert_eq!(game.ply(), 6);
+    }
+
+    #[test]
+    fn seeking_stops_at_either_end() {
+        let mut game = game_after("4433521");
+        let end = snapshot(&game);
+
+        game.seek(0);
+        assert_eq!(game.ply(), 0);
+        assert_eq!(snapshot(&game), snapshot(&game_after("")));
+
+        game.seek(3);
+        assert_eq!(snapshot(&game), snapshot(&game_after("443")));
+
+        game.seek(100);
+        assert_eq!(game.ply(), 7);
+        assert_eq!(snapshot(&game), end);
+    }
+
+    #[test]
+    fn undoing_past_the_start_does_nothing() {
+        let mut game = game_after("");
+        let start = snapshot(&game);
+        assert!(!game.can_undo());
+        assert_eq!(game.undo(), None);
+        assert_eq!(snapshot(&game), start);
+
+        let mut game = game_after("4");
+        assert!(game.undo().is_some());
+        assert_eq!(game.undo(), None);
+        assert_eq!(snapshot(&game), start);
+        assert_eq!(game.plies(), 1);
+    }
+
+    #[test]
+    fn undo_stops_at_a_set_position() {
+        let (board, turn) = Board::from_notation("6x7x4 7/7/7/7/3y3/3r3 r").unwrap();
+        let mut game = game_after("4433");
+        game.set_position(board.clone(), turn);
+        assert_eq!(game.ply(), 0);
+        assert!(!game.can_undo() && !game.can_redo());
+        assert_eq!(game.start().as_deref(), Some("6x7x4 7/7/7/7/3y3/3r3 r"));
+
+        game.play_notation("34").unwrap();
+        assert_eq!(game.turn(), Turn::Player1);
+        game.seek(0);
+        assert_eq!(game.undo(), None);
+        assert_eq!(
+            game.board().to_notation(&game.turn()),
+            board.to_notation(&turn)
+        );
+        assert_eq!(game.board().hash(), board.hash());
+
+        // Starting over goes back to an empty board
+        game.reset();
+        assert_eq!(game.start(), None);
+        assert_eq!(game.board().piece_count(), 0);
+    }
+}
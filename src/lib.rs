@@ -0,0 +1,86 @@
+//! The rules and agents for Connect X, independent of any front end.
+
+pub mod agent;
+mod bitboard;
+pub mod board;
+pub mod cli;
+pub mod editor;
+pub mod external;
+pub mod game;
+pub mod mcts;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod net;
+pub mod notation;
+pub mod protocol;
+pub mod save;
+pub mod shape;
+pub mod solver;
+pub mod tournament;
+pub mod transposition;
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// Board Limits
+pub const MAX_ROW: usize = 500;
+pub const MAX_COL: usize = 500;
+
+// Player Limits
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 8;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Turn {
+    Player1,
+    Player2,
+    Player3,
+    Player4,
+    Player5,
+    Player6,
+    Player7,
+    Player8,
+}
+
+impl Turn {
+    pub const ALL: [Turn; MAX_PLAYERS] = [
+        Turn::Player1,
+        Turn::Player2,
+        Turn::Player3,
+        Turn::Player4,
+        Turn::Player5,
+        Turn::Player6,
+        Turn::Player7,
+        Turn::Player8,
+    ];
+
+    /// The players taking part in a game of `players`, in turn order.
+    pub fn all(players: usize) -> &'static [Turn] {
+        &Turn::ALL[..players]
+    }
+
+    /// The player's position in the turn order, counting from 0
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn from_index(index: usize) -> Self {
+        Turn::ALL[index]
+    }
+
+    /// Whose turn it is after this player's, in a game of `players`
+    pub fn next(&self, players: usize) -> Self {
+        Turn::from_index((self.index() + 1) % players)
+    }
+
+    /// Whose turn it was before this player's, in a game of `players`
+    pub fn previous(&self, players: usize) -> Self {
+        Turn::from_index((self.index() + players - 1) % players)
+    }
+}
+
+impl fmt::Display for Turn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Player {}", self.index() + 1)
+    }
+}
@@ -1,31 +1,118 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use connect_x::{
+    agent::Agent,
+    board::{Action, GameState},
+    cli::Settings,
+    editor,
+    game::{Game, Move},
+    save::SaveFile,
+    shape::Shape,
+    transposition::TranspositionTable,
+    Turn, MAX_COL, MAX_PLAYERS, MAX_ROW, MIN_PLAYERS,
+};
 use macroquad::prelude::*;
 
-use agent::{compute_turn, Agent};
-use board::{Board, GameState};
-
-mod agent;
-mod board;
+mod network;
+mod render;
+mod storage;
 mod ui;
+mod worker;
+
+use network::{Change, Network};
+use worker::Worker;
 
 const DEBUG: bool = false;
 
 // Game Constants
 const LEFT_BUFFER: f32 = 250.0;
 const WINDOW_WIDTH: f32 = 225.0;
-const MAX_ROW: usize = 500;
-const MAX_COL: usize = 500;
+const MAX_TABLE_MB: usize = 1024;
+const DEFAULT_SAVE_NAME: &str = "connect-x-save.json";
+
+fn compute_turn(
+    game: &mut Game,
+    agent: &Agent,
+    table: &Arc<Mutex<TranspositionTable>>,
+    worker: &mut Option<Worker>,
+    agent_status: &mut Option<String>,
+    piece_size: f32,
+    colors: &[[f32; 3]],
+) -> Option<Action> {
+    let turn = game.turn();
+    let chosen_move = match agent {
+        Agent::Player => render::player_turn(game.board(), &turn, piece_size, colors),
+        // The move arrives over the network
+        Agent::Remote => None,
+        _ => {
+            let thinking = worker
+                .get_or_insert_with(|| Worker::spawn(agent, game.board(), &turn, table.clone()));
+            let result = thinking.poll()?;
+            *agent_status = thinking.status().map(|status| format!("{turn}: {status}"));
+            *worker = None;
+            result
+        }
+    };
+    match chosen_move {
+        Some(action) if game.play(action) => chosen_move,
+        _ => None,
+    }
+}
+
+/// Undoes or redoes moves with `step` until it is a human's turn again, so
+/// that undoing against the computer doesn't just have it play straight
+/// away. Between computer agents a single move is stepped over.
+fn step_history(game: &mut Game, agents: &[Agent], step: fn(&mut Game) -> Option<Move>) {
+    let is_human = |turn: Turn| agents[turn.index()] == Agent::Player;
+    let any_human = Turn::all(game.board().players())
+        .iter()
+        .any(|&turn| is_human(turn));
+
+    while step(game).is_some() {
+        if !any_human || is_human(game.turn()) {
+            break;
+        }
+    }
+}
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub enum Turn {
-    Player1,
-    Player2,
+/// Reads the save called `name` and replays it.
+fn load_game(name: &str) -> Result<(SaveFile, Game), Box<dyn Error>> {
+    let save = SaveFile::from_json(&storage::read(name)?)?;
+    let game = save.to_game()?;
+    Ok((save, game))
 }
 
-impl Turn {
-    fn next(&self) -> Self {
-        match self {
-            Turn::Player1 => Turn::Player2,
-            Turn::Player2 => Turn::Player1,
+/// The settings given on the command line, or in the page's URL on the web.
+fn launch_settings() -> Settings {
+    #[cfg(not(target_arch = "wasm32"))]
+    use connect_x::cli::{CliError, USAGE};
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let args = std::env::args();
+    #[cfg(target_arch = "wasm32")]
+    let args = quad_url::get_program_parameters().into_iter();
+
+    match Settings::from_args(args.skip(1)) {
+        Ok(settings) => settings,
+        #[cfg(not(target_arch = "wasm32"))]
+        Err(CliError::Help) => {
+            println!("Usage: connect-x [OPTIONS]\n\n{USAGE}");
+            std::process::exit(0);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Err(err) => {
+            eprintln!("error: {err}\n\nRun with --help to see the options");
+            std::process::exit(2);
+        }
+        // There's no terminal on the web, so bad parameters are only logged to
+        // the console and the defaults used instead
+        #[cfg(target_arch = "wasm32")]
+        Err(err) => {
+            error!("{err}");
+            Settings::default()
         }
     }
 }
@@ -38,25 +125,46 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+fn main() {
+    // Read the settings before the window opens, so that --help and mistakes
+    // are reported straight away
+    let settings = launch_settings();
+    macroquad::Window::from_config(window_conf(), run(settings));
+}
+
+async fn run(settings: Settings) {
+    // So the random agents don't play the same games every time
+    rand::srand((miniquad::date::now() * 1e6) as u64);
+
     // Game Settings
-    let mut rows: usize = 6;
-    let mut cols: usize = 7;
-    let mut x_val: usize = 4;
-    let mut player_one = Agent::Player;
-    let mut player_two = Agent::Player;
+    let mut rows: usize = settings.rows;
+    let mut cols: usize = settings.cols;
+    let mut x_val: usize = settings.x_val;
+    let mut players: usize = settings.players;
+    let mut rules = settings.rules;
+    let mut agents = settings.agents;
+    let mut colors = render::DEFAULT_COLORS;
+    let mut table_mb: usize = 16;
 
     // Game Variables
-    let mut board = Board::new(rows, cols);
-    let mut current_turn: Turn = Turn::Player1;
-    let mut running: bool = false;
+    let mut game = Game::new(rows, cols);
+    game.set_shape(&settings.shape.blocked(rows, cols));
+    let mut editing = false;
+    let table = Arc::new(Mutex::new(TranspositionTable::new(table_mb)));
+    let mut worker: Option<Worker> = None;
+    // What the last computer agent had to say about its move
+    let mut agent_status: Option<String> = None;
+    let mut running: bool = settings.autostart;
     let mut gamestate = GameState::OnGoing;
-    let mut sleep_time: f64 = 0.0;
+    let mut sleep_time: f64 = settings.sleep_time;
     let mut time_counter: f64 = get_time();
+    let mut save_name = DEFAULT_SAVE_NAME.to_owned();
+    let mut save_status = String::new();
+    let mut autoplay = false;
+    let mut network = Network::new();
 
     // Debug Info
-    let mut selected_move: usize = 0;
+    let mut selected_move: Option<Action> = None;
 
     loop {
         // Calculate Square Size for rendering
@@ -69,7 +177,25 @@ async fn main() {
         let max_x = rows.min(cols);
 
         // Resize the board if needed
-        board.verify(rows, cols, x_val);
+        game.verify(rows, cols, x_val);
+        game.set_players(players);
+        game.set_rules(rules);
+        if !running {
+            table.lock().unwrap().verify(table_mb);
+        }
+
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        let mut undo_requested = ctrl && is_key_pressed(KeyCode::Z);
+        let mut redo_requested = ctrl && is_key_pressed(KeyCode::Y);
+        let mut load_requested = false;
+        let mut replay_changed = false;
+        // A joined game belongs to the host, so it can't be changed from here
+        let following = network.is_client();
+
+        let winning_lines = match gamestate {
+            GameState::Win(_) => game.board().winning_lines(),
+            GameState::OnGoing | GameState::Tie => vec![],
+        };
 
         clear_background(WHITE);
 
@@ -81,7 +207,7 @@ async fn main() {
                 .default_size([WINDOW_WIDTH, 1.0])
                 .anchor(egui::Align2::LEFT_TOP, [0.0, 0.0])
                 .resizable(false)
-                .enabled(!running)
+                .enabled(!running && !following)
                 .show(egui_ctx, |ui| {
                     let size = ui.available_size();
                     settings_height = size[1] + 45.0;
@@ -101,18 +227,72 @@ async fn main() {
                     ui::number_drag(ui, &mut rows, "Rows:", 1..=MAX_ROW);
                     ui::number_drag(ui, &mut cols, "Cols:", 1..=MAX_COL);
                     ui::number_drag(ui, &mut x_val, "X Val:", 1..=max_x);
+                    ui.checkbox(&mut rules.gravity, "Gravity");
+                    ui.add_enabled(
+                        rules.gravity,
+                        egui::Checkbox::new(&mut rules.popout, "PopOut (right click to pop)"),
+                    );
+                    ui.checkbox(&mut rules.wrap_horizontal, "Wrap left and right");
+                    ui.checkbox(&mut rules.wrap_vertical, "Wrap top and bottom");
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label("Shape:");
+                        for shape in Shape::ALL {
+                            if ui.button(shape.name()).clicked() {
+                                game.set_shape(&shape.blocked(rows, cols));
+                            }
+                        }
+                    });
+                    ui.checkbox(&mut editing, "Edit position (click cells)");
+                    if editing {
+                        let mut turn = game.turn();
+                        egui::ComboBox::from_label("To move")
+                            .selected_text(turn.to_string())
+                            .show_ui(ui, |ui| {
+                                for &option in Turn::all(players) {
+                                    ui.selectable_value(&mut turn, option, option.to_string());
+                                }
+                            });
+                        if turn != game.turn() {
+                            game.set_position(game.board().clone(), turn);
+                        }
+                        let check = editor::check_position(game.board(), &turn);
+                        match &check {
+                            Ok(()) => ui.label("The position can come up in a game"),
+                            Err(err) => ui.colored_label(egui::Color32::RED, err.to_string()),
+                        };
+                        if ui
+                            .add_enabled(check.is_ok(), egui::Button::new("Play from here"))
+                            .clicked()
+                        {
+                            editing = false;
+                            game.set_position(game.board().clone(), turn);
+                            gamestate = game.board().game_state();
+                            running = gamestate == GameState::OnGoing;
+                            time_counter = get_time();
+                            table.lock().unwrap().clear();
+                            network.sync(&game);
+                        }
+                    }
                     ui.add(egui::Slider::new(&mut sleep_time, 0.0..=5.0).text("Sleep"));
 
-                    ui::agent_selector(ui, "Player 1", &mut player_one);
-                    ui::agent_selector(ui, "Player 2", &mut player_two);
+                    ui::number_drag(ui, &mut players, "Players:", MIN_PLAYERS..=MAX_PLAYERS);
+                    for (turn, (agent, color)) in Turn::all(players)
+                        .iter()
+                        .zip(agents.iter_mut().zip(colors.iter_mut()))
+                    {
+                        ui::agent_selector(ui, &turn.to_string(), agent, color);
+                    }
+                    ui::number_drag(ui, &mut table_mb, "Table MB:", 1..=MAX_TABLE_MB);
 
                     ui.separator();
 
                     ui.centered_and_justified(|ui| {
                         if ui.button("Start").clicked() {
                             running = true;
-                            current_turn = Turn::Player1;
-                            board.reset();
+                            agent_status = None;
+                            game.reset();
+                            table.lock().unwrap().clear();
+                            network.sync(&game);
                         }
                     });
                 });
@@ -128,51 +308,248 @@ async fn main() {
                         ui.separator();
                     }
 
-                    match current_turn {
-                        Turn::Player1 => ui.label("Current Turn: Player 1"),
-                        Turn::Player2 => ui.label("Current Turn: Player 2"),
-                    };
+                    ui.label(format!("Current Turn: {}", game.turn()));
 
                     ui.label(format!("GameState: {gamestate:?}"));
+                    for line in &winning_lines {
+                        let (start, end) = (line.start, line.end());
+                        ui.label(format!(
+                            "Winning line: ({}, {}) to ({}, {})",
+                            start.0 + 1,
+                            start.1 + 1,
+                            end.0 + 1,
+                            end.1 + 1
+                        ));
+                    }
+
+                    if let Some(status) = &agent_status {
+                        ui.label(status);
+                    }
+
+                    ui.collapsing("Moves", |ui| ui::move_list(ui, &game));
+
+                    ui.collapsing("Notation", |ui| {
+                        ui::copyable_text(ui, "Moves:", game.to_move_notation());
+                        ui::copyable_text(ui, "Position:", game.board().to_notation(&game.turn()));
+                    });
+
+                    if worker.is_some() {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Spinner::new());
+                            ui.label("Thinking…");
+                        });
+                    }
+                    let waiting_on_remote = agents[game.turn().index()] == Agent::Remote;
+                    if running && waiting_on_remote {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Spinner::new());
+                            ui.label("Waiting for the remote player…");
+                        });
+                    }
+
+                    network.ui(ui);
+
+                    if !running && game.plies() > 0 {
+                        ui.collapsing("Replay", |ui| {
+                            replay_changed = ui::replay_controls(ui, &mut game, &mut autoplay);
+                            // Carrying on from earlier in the game drops the
+                            // moves after it once the next one is made
+                            if ui
+                                .add_enabled(
+                                    game.board().game_state() == GameState::OnGoing && !following,
+                                    egui::Button::new("Play from here"),
+                                )
+                                .clicked()
+                            {
+                                autoplay = false;
+                                running = true;
+                                time_counter = get_time();
+                            }
+                        });
+                    }
+
+                    ui.collapsing("Save", |ui| {
+                        ui.text_edit_singleline(&mut save_name);
+                        ui.horizontal(|ui| {
+                            if ui.button("Save").clicked() {
+                                let save = SaveFile::new(&game, &agents, sleep_time);
+                                save_status = match storage::write(&save_name, &save.to_json()) {
+                                    Ok(()) => format!("Saved to {save_name}"),
+                                    Err(err) => format!("Could not save: {err}"),
+                                };
+                            }
+                            if ui
+                                .add_enabled(!following, egui::Button::new("Load"))
+                                .clicked()
+                            {
+                                load_requested = true;
+                            }
+                        });
+                        if !save_status.is_empty() {
+                            ui.label(&save_status);
+                        }
+                    });
 
                     ui.separator();
 
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(game.can_undo() && !following, egui::Button::new("Undo"))
+                            .clicked()
+                        {
+                            undo_requested = true;
+                        }
+                        if ui
+                            .add_enabled(game.can_redo() && !following, egui::Button::new("Redo"))
+                            .clicked()
+                        {
+                            redo_requested = true;
+                        }
+                    });
+
                     ui.centered_and_justified(|ui| {
                         if ui
                             .add_enabled(running, egui::Button::new("End Game"))
                             .clicked()
                         {
                             running = false;
+                            worker = None;
                         }
                     });
                 });
         });
 
-        board.draw(square_size);
+        if following {
+            undo_requested = false;
+            redo_requested = false;
+        }
+
+        if undo_requested || redo_requested {
+            // Whatever the agent was thinking about is out of date now
+            worker = None;
+            agent_status = None;
+            autoplay = false;
+            let step = if undo_requested {
+                Game::undo
+            } else {
+                Game::redo
+            };
+            step_history(&mut game, &agents, step);
+            gamestate = game.board().game_state();
+            // Once the game has stopped, stepping through it only replays it
+            // until the last move, so the moves after aren't thrown away
+            running = gamestate == GameState::OnGoing && (running || game.ply() == game.plies());
+            time_counter = get_time();
+            network.sync(&game);
+        }
+
+        if load_requested {
+            match load_game(&save_name) {
+                Ok((save, loaded)) => {
+                    worker = None;
+                    agent_status = None;
+                    rows = save.rows;
+                    cols = save.cols;
+                    x_val = save.x_val;
+                    players = save.players;
+                    rules = save.rules;
+                    agents[..players].clone_from_slice(&save.agents);
+                    sleep_time = save.sleep_time;
+                    game = loaded;
+                    table.lock().unwrap().clear();
+                    gamestate = game.board().game_state();
+                    running = gamestate == GameState::OnGoing;
+                    time_counter = get_time();
+                    save_status = format!("Loaded {save_name}");
+                    network.sync(&game);
+                }
+                Err(err) => save_status = format!("Could not load: {err}"),
+            }
+        }
+
+        if running || game.ply() == game.plies() {
+            autoplay = false;
+        }
+        if autoplay && get_time() - time_counter >= sleep_time {
+            game.redo();
+            replay_changed = true;
+        }
+        if replay_changed {
+            gamestate = game.board().game_state();
+            time_counter = get_time();
+        }
+
+        let remote_seats: Vec<Turn> = Turn::all(players)
+            .iter()
+            .copied()
+            .filter(|turn| agents[turn.index()] == Agent::Remote)
+            .collect();
+        for change in network.poll(&mut game, &remote_seats) {
+            match change {
+                Change::Move => {}
+                Change::Sync(seat) => {
+                    worker = None;
+                    autoplay = false;
+                    let board = game.board();
+                    (rows, cols, x_val) = (board.rows(), board.cols(), board.x_to_win());
+                    players = board.players();
+                    rules = board.rules();
+                    // Everyone else plays from elsewhere
+                    for (&turn, agent) in Turn::all(players).iter().zip(agents.iter_mut()) {
+                        if Some(turn) != seat {
+                            *agent = Agent::Remote;
+                        } else if *agent == Agent::Remote {
+                            *agent = Agent::Player;
+                        }
+                    }
+                    table.lock().unwrap().clear();
+                }
+            }
+            gamestate = game.board().game_state();
+            running = gamestate == GameState::OnGoing;
+            time_counter = get_time();
+        }
+
+        render::draw_board(game.board(), square_size, &colors);
+
+        // Clicking a cell while editing cycles it through the pieces
+        if editing && !running && !following && is_mouse_button_pressed(MouseButton::Left) {
+            if let Some((row, col)) = render::cell_at(game.board(), square_size, mouse_position()) {
+                let mut board = game.board().clone();
+                editor::cycle_cell(&mut board, row, col);
+                game.set_position(board, game.turn());
+                gamestate = game.board().game_state();
+            }
+        }
+        render::draw_winning_lines(&winning_lines, square_size);
 
         // Calculate turns
         if running {
             if get_time() - time_counter >= sleep_time {
-                let start_turn = current_turn;
-                let cur_agent = match current_turn {
-                    Turn::Player1 => &player_one,
-                    Turn::Player2 => &player_two,
-                };
-                if let Some(col) =
-                    compute_turn(&mut current_turn, cur_agent, &mut board, square_size)
-                {
-                    selected_move = col;
+                let start_turn = game.turn();
+                let cur_agent = &agents[start_turn.index()];
+                if let Some(action) = compute_turn(
+                    &mut game,
+                    cur_agent,
+                    &table,
+                    &mut worker,
+                    &mut agent_status,
+                    square_size,
+                    &colors,
+                ) {
+                    selected_move = Some(action);
+                    network.moved(&game);
                 }
 
-                if current_turn != start_turn {
+                if game.turn() != start_turn {
                     time_counter = get_time();
                 }
             }
 
-            gamestate = board.game_state();
+            gamestate = game.board().game_state();
 
             match gamestate {
-                GameState::Tie | GameState::P1Win | GameState::P2Win => running = false,
+                GameState::Tie | GameState::Win(_) => running = false,
                 GameState::OnGoing => {}
             }
         }
@@ -0,0 +1,292 @@
+//! Monte Carlo Tree Search using UCT and random playouts.
+//!
+//! Positions are judged only by playing random games to the end, so the agent
+//! needs no evaluation function and works for any board size or `x_to_win`.
+//! Every node is judged by the rewards of the player who moved into it, so
+//! with more than two players each one plays for their own win (max-n).
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::{Action, Board, GameState},
+    Turn,
+};
+
+/// Exploration constant for UCT, sqrt(2) being the textbook choice
+const EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+/// When the search stops and plays its most visited move
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MctsLimit {
+    /// Think for this many seconds
+    Time(f64),
+    /// Run this many playouts
+    Iterations(u32),
+}
+
+impl Default for MctsLimit {
+    fn default() -> Self {
+        MctsLimit::Time(1.0)
+    }
+}
+
+struct Node {
+    /// The move made to reach this node
+    action: Option<Action>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Moves that have no child node yet
+    untried: Vec<Action>,
+    /// The player who made the move into this node
+    player: Turn,
+    /// Set when the move into this node ended the game
+    outcome: Option<Outcome>,
+    visits: u32,
+    /// Total reward for `player`, 1 for each win and 0.5 for each tie
+    reward: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Win(Turn),
+    Tie,
+}
+
+impl Outcome {
+    /// The outcome of a game in `state`, if it is over
+    fn of(state: GameState) -> Option<Outcome> {
+        match state {
+            GameState::Win(winner) => Some(Outcome::Win(winner)),
+            GameState::Tie => Some(Outcome::Tie),
+            GameState::OnGoing => None,
+        }
+    }
+
+    fn reward_for(&self, player: Turn) -> f64 {
+        match self {
+            Outcome::Win(winner) if *winner == player => 1.0,
+            Outcome::Win(_) => 0.0,
+            Outcome::Tie => 0.5,
+        }
+    }
+}
+
+/// A search tree rooted at the position the agent is choosing a move for.
+///
+/// Nodes are stored in a flat list and refer to each other by index. Each
+/// iteration plays the moves down the tree on a single board and undoes them
+/// afterwards, so the board is never cloned.
+pub struct Tree {
+    board: Board,
+    nodes: Vec<Node>,
+    iterations: u32,
+}
+
+impl Tree {
+    pub fn new(board: &Board, turn: &Turn) -> Self {
+        let root = Node {
+            action: None,
+            parent: None,
+            children: vec![],
+            untried: board.moves(turn),
+            player: turn.previous(board.players()),
+            outcome: None,
+            visits: 0,
+            reward: 0.0,
+        };
+
+        Tree {
+            board: board.clone(),
+            nodes: vec![root],
+            iterations: 0,
+        }
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// The root move that has been explored the most, or `None` if there are
+    /// no moves to make.
+    pub fn best_move(&self) -> Option<Action> {
+        self.nodes[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| self.nodes[child].visits)
+            .and_then(|&child| self.nodes[child].action)
+            .or_else(|| self.nodes[0].untried.first().copied())
+    }
+
+    /// Runs one select, expand, playout and backpropagate cycle.
+    pub fn iterate(&mut self) {
+        let mut played = vec![];
+
+        // Select
+        let mut node = 0;
+        while self.nodes[node].untried.is_empty()
+            && !self.nodes[node].children.is_empty()
+            && self.nodes[node].outcome.is_none()
+        {
+            node = self.select_child(node);
+            let n = &self.nodes[node];
+            let action = n.action.unwrap();
+            self.board.play(action, &n.player);
+            played.push((action, n.player));
+        }
+
+        // Expand
+        if self.nodes[node].outcome.is_none() && !self.nodes[node].untried.is_empty() {
+            let untried = &mut self.nodes[node].untried;
+            let action = untried.swap_remove(quad_rand::gen_range(0, untried.len()));
+            let player = self.nodes[node].player.next(self.board.players());
+            self.board.play(action, &player);
+            played.push((action, player));
+
+            let outcome = Outcome::of(self.board.game_state());
+            self.nodes.push(Node {
+                action: Some(action),
+                parent: Some(node),
+                children: vec![],
+                untried: if outcome.is_none() {
+                    self.board.moves(&player.next(self.board.players()))
+                } else {
+                    vec![]
+                },
+                player,
+                outcome,
+                visits: 0,
+                reward: 0.0,
+            });
+            let child = self.nodes.len() - 1;
+            self.nodes[node].children.push(child);
+            node = child;
+        }
+
+        // Playout
+        let outcome = match self.nodes[node].outcome {
+            Some(outcome) => outcome,
+            None => {
+                let next = self.nodes[node].player.next(self.board.players());
+                self.playout(next, &mut played)
+            }
+        };
+
+        // Backpropagate
+        let mut current = Some(node);
+        while let Some(i) = current {
+            let n = &mut self.nodes[i];
+            n.visits += 1;
+            n.reward += outcome.reward_for(n.player);
+            current = n.parent;
+        }
+
+        for (action, player) in played.into_iter().rev() {
+            self.board.take_back(action, &player);
+        }
+        self.iterations += 1;
+    }
+
+    /// Picks the child with the highest upper confidence bound.
+    fn select_child(&self, node: usize) -> usize {
+        let log_visits = (self.nodes[node].visits as f64).ln();
+        let uct = |&child: &usize| {
+            let n = &self.nodes[child];
+            n.reward / n.visits as f64 + EXPLORATION * (log_visits / n.visits as f64).sqrt()
+        };
+
+        *self.nodes[node]
+            .children
+            .iter()
+            .max_by(|a, b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+
+    /// Plays random moves until the game ends, recording them in `played`.
+    fn playout(&mut self, mut turn: Turn, played: &mut Vec<(Action, Turn)>) -> Outcome {
+        loop {
+            let moves = self.board.moves(&turn);
+            if moves.is_empty() {
+                return Outcome::Tie;
+            }
+
+            let action = moves[quad_rand::gen_range(0, moves.len())];
+            self.board.play(action, &turn);
+            played.push((action, turn));
+            if let Some(outcome) = Outcome::of(self.board.game_state()) {
+                return outcome;
+            }
+            turn = turn.next(self.board.players());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Rules;
+
+    /// The tree for `position` after `iterations` playouts
+    fn search(position: &str, rules: Rules, iterations: u32) -> (Tree, Board, Turn) {
+        let (board, turn) = Board::from_notation_with(position, rules).unwrap();
+        let mut tree = Tree::new(&board, &turn);
+        for _ in 0..iterations {
+            tree.iterate();
+        }
+        (tree, board, turn)
+    }
+
+    #[test]
+    fn takes_a_win_in_one() {
+        let (tree, ..) = search("6x7x4 7/7/7/7/yyy4/rrr4 r", Rules::default(), 2000);
+        assert_eq!(tree.best_move(), Some(Action::Drop(3)));
+        assert_eq!(tree.iterations(), 2000);
+    }
+
+    #[test]
+    fn blocks_a_loss_in_one() {
+        let (tree, ..) = search("6x7x4 7/7/7/7/y6/yrrr3 y", Rules::default(), 5000);
+        assert_eq!(tree.best_move(), Some(Action::Drop(4)));
+    }
+
+    #[test]
+    fn iterations_leave_the_board_as_it_was() {
+        let (tree, board, turn) = search("6x7x4 7/7/7/7/3y3/2yr3 r", Rules::default(), 500);
+        assert_eq!(tree.board.to_notation(&turn), board.to_notation(&turn));
+        assert_eq!(tree.board.hash(), board.hash());
+    }
+
+    #[test]
+    fn plays_legal_moves_with_popout() {
+        let rules = Rules {
+            popout: true,
+            ..Rules::default()
+        };
+        // Yellow has nothing to drop into, so has to pop
+        let (tree, board, turn) = search("3x3x3 ryr/ryr/yry y", rules, 200);
+        let chosen = tree.best_move().unwrap();
+        assert!(matches!(chosen, Action::Pop(_)));
+        assert!(board.moves(&turn).contains(&chosen));
+
+        let (tree, board, turn) = search("6x7x4 7/7/7/7/3y3/2yr3 r", rules, 500);
+        assert!(board.moves(&turn).contains(&tree.best_move().unwrap()));
+    }
+
+    #[test]
+    fn plays_legal_moves_without_gravity() {
+        let rules = Rules {
+            gravity: false,
+            ..Rules::default()
+        };
+        let (tree, board, turn) = search("3x3x3 r1y/1r1/y2 y", rules, 2000);
+        let chosen = tree.best_move().unwrap();
+        assert!(board.moves(&turn).contains(&chosen));
+        // Yellow has to stop the diagonal
+        assert_eq!(chosen, Action::Put(2, 2));
+    }
+
+    #[test]
+    fn a_full_board_has_no_move() {
+        let (tree, ..) = search("3x3x3 ryr/ryr/yry y", Rules::default(), 10);
+        assert_eq!(tree.best_move(), None);
+    }
+}
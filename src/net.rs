@@ -0,0 +1,887 @@
+//! Playing over the network, with one instance hosting the game and others
+//! joining it by address.
+//!
+//! The host's [`Game`] is the real one. Each side sends the moves it makes
+//! and the host checks every move from a client before passing it on, sending
+//! the whole game back to any client that has fallen out of step. A client
+//! that loses its connection keeps trying to reconnect, and gets the whole
+//! game again once it does.
+//!
+//! Clients can also join as spectators, who are sent every move but never
+//! given a side to play.
+//!
+//! Messages are JSON objects, one per line, over TCP. There is no WebSocket
+//! transport, so a browser can't take part and the web build leaves
+//! networking out.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::{Action, Board, GameState, Rules},
+    game::Game,
+    Turn, MAX_COL, MAX_PLAYERS, MAX_ROW, MIN_PLAYERS,
+};
+
+/// The port used when an address doesn't give one
+pub const DEFAULT_PORT: u16 = 4004;
+
+/// How long a client waits between attempts to reconnect
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client waits for the host to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The longest message accepted, in bytes. The whole game on the largest
+/// board is a few megabytes, so anything longer isn't a message at all.
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// The first message a client sends on every connection
+    Hello {
+        #[serde(default)]
+        spectator: bool,
+    },
+    /// The whole game, sent by the host when a client connects and whenever
+    /// the game is changed other than by a move
+    State {
+        rows: usize,
+        cols: usize,
+        x_to_win: usize,
+        #[serde(default = "two_players")]
+        players: usize,
+        #[serde(default)]
+        rules: Rules,
+        #[serde(default)]
+        blocked: Vec<(usize, usize)>,
+        /// The position the moves were played from, if not an empty board
+        #[serde(default)]
+        start: Option<String>,
+        moves: Vec<Action>,
+        /// The side the client plays, `None` if every side is taken
+        seat: Option<Turn>,
+    },
+    /// `action` made as move number `ply`, counting from 0
+    Move { ply: usize, action: Action },
+}
+
+fn two_players() -> usize {
+    2
+}
+
+/// Something that happened on the network that the front end should act on
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A client connected to the host, playing the side given or, with `None`,
+    /// only watching
+    Joined(Option<Turn>),
+    /// A client disconnected from the host
+    Left(Option<Turn>),
+    Connected,
+    Disconnected,
+    /// The client should replace its game with the host's, in which it plays
+    /// `seat`
+    Sync {
+        game: Box<Game>,
+        seat: Option<Turn>,
+    },
+    /// The player on the other end made `action`, which is a legal move
+    Move(Action),
+}
+
+/// Adds [`DEFAULT_PORT`] to an address that doesn't have a port.
+pub fn resolve(address: &str) -> io::Result<SocketAddr> {
+    let with_port = if address.contains(':') {
+        address.to_owned()
+    } else {
+        format!("{address}:{DEFAULT_PORT}")
+    };
+    with_port
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "the address didn't resolve"))
+}
+
+/// A connection sending and receiving messages without blocking.
+struct Link {
+    stream: TcpStream,
+    received: Vec<u8>,
+    /// How much of `received` comes after the last complete message
+    partial: usize,
+    unsent: Vec<u8>,
+}
+
+impl Link {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Link {
+            stream,
+            received: vec![],
+            partial: 0,
+            unsent: vec![],
+        })
+    }
+
+    fn send(&mut self, message: &Message) {
+        serde_json::to_writer(&mut self.unsent, message).expect("messages are always valid JSON");
+        self.unsent.push(b'\n');
+    }
+
+    /// Sends as much of what is waiting to go as the connection will take.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.unsent.drain(..n);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends anything waiting to go and returns the messages that have
+    /// arrived. An error means the connection is finished with.
+    fn poll(&mut self) -> io::Result<Vec<Message>> {
+        self.flush()?;
+
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    let chunk = &buffer[..n];
+                    self.received.extend_from_slice(chunk);
+                    self.partial = match chunk.iter().rposition(|&b| b == b'\n') {
+                        Some(end) => n - end - 1,
+                        None => self.partial + n,
+                    };
+                    if self.partial > MAX_MESSAGE_LEN {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            "message is too long",
+                        ));
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut messages = vec![];
+        while let Some(end) = self.received.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.received.drain(..=end).collect();
+            let message = serde_json::from_slice(&line)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+}
+
+/// Whether `action` can be made next in `game`
+fn is_legal(game: &Game, action: Action) -> bool {
+    game.board().game_state() == GameState::OnGoing
+        && game.board().moves(&game.turn()).contains(&action)
+}
+
+/// A new game set up as described by the host, if it can be played
+fn setup(
+    rows: usize,
+    cols: usize,
+    x_to_win: usize,
+    players: usize,
+    rules: Rules,
+    blocked: &[(usize, usize)],
+    start: Option<&str>,
+) -> Option<Game> {
+    if !(1..=MAX_ROW).contains(&rows)
+        || !(1..=MAX_COL).contains(&cols)
+        || !(1..=rows.min(cols)).contains(&x_to_win)
+        || !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players)
+        || blocked.iter().any(|&(row, col)| row >= rows || col >= cols)
+    {
+        return None;
+    }
+
+    let mut game = Game::new(rows, cols);
+    game.verify(rows, cols, x_to_win);
+    game.set_players(players);
+    game.set_rules(rules);
+    game.set_shape(blocked);
+    if let Some(start) = start {
+        let (board, turn) = Board::from_notation_with(start, rules).ok()?;
+        if board.rows() != rows
+            || board.cols() != cols
+            || board.x_to_win() != x_to_win
+            || board.players() != players
+            || board.blocked_cells() != game.board().blocked_cells()
+        {
+            return None;
+        }
+        game.set_position(board, turn);
+    }
+    Some(game)
+}
+
+/// The game made by playing `moves` on `game`, if they are all legal
+fn replay(mut game: Game, moves: &[Action]) -> Option<Game> {
+    for &action in moves {
+        if !is_legal(&game, action) {
+            return None;
+        }
+        game.play(action);
+    }
+    Some(game)
+}
+
+fn state(game: &Game, seat: Option<Turn>) -> Message {
+    let board = game.board();
+    Message::State {
+        rows: board.rows(),
+        cols: board.cols(),
+        x_to_win: board.x_to_win(),
+        players: board.players(),
+        rules: board.rules(),
+        blocked: board.blocked_cells(),
+        start: game.start(),
+        moves: game.history().iter().map(|m| m.action).collect(),
+        seat,
+    }
+}
+
+/// The last move of `game` as a message
+fn last_move(game: &Game) -> Option<Message> {
+    let last = game.history().last()?;
+    Some(Message::Move {
+        ply: game.ply() - 1,
+        action: last.action,
+    })
+}
+
+struct Connection {
+    link: Link,
+    greeted: bool,
+    spectator: bool,
+    seat: Option<Turn>,
+}
+
+/// The hosting side, which holds the real game.
+pub struct Host {
+    listener: TcpListener,
+    connections: Vec<Connection>,
+}
+
+impl Host {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(resolve(address)?)?;
+        listener.set_nonblocking(true)?;
+        Ok(Host {
+            listener,
+            connections: vec![],
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The sides that have a client playing them
+    pub fn seated(&self) -> Vec<Turn> {
+        self.connections.iter().filter_map(|c| c.seat).collect()
+    }
+
+    /// How many clients are only watching
+    pub fn spectators(&self) -> usize {
+        self.connections
+            .iter()
+            .filter(|c| c.greeted && c.seat.is_none())
+            .count()
+    }
+
+    /// Accepts new clients and handles their messages. `remote_seats` are the
+    /// sides played by clients, which are handed out in order as they join.
+    pub fn poll(&mut self, game: &Game, remote_seats: &[Turn]) -> Vec<Event> {
+        let mut events = vec![];
+
+        // Stops once nobody is waiting, or on a connection that failed before
+        // it was accepted, which is simply dropped
+        while let Ok((stream, _)) = self.listener.accept() {
+            if let Ok(link) = Link::new(stream) {
+                self.connections.push(Connection {
+                    link,
+                    greeted: false,
+                    spectator: false,
+                    seat: None,
+                });
+            }
+        }
+
+        let mut i = 0;
+        while i < self.connections.len() {
+            let messages = match self.connections[i].link.poll() {
+                Ok(messages) => messages,
+                Err(_) => {
+                    let gone = self.connections.remove(i);
+                    if gone.greeted {
+                        events.push(Event::Left(gone.seat));
+                    }
+                    continue;
+                }
+            };
+
+            for message in messages {
+                match message {
+                    Message::Hello { spectator } => {
+                        let taken = self.seated();
+                        let connection = &mut self.connections[i];
+                        if !connection.greeted {
+                            connection.greeted = true;
+                            connection.spectator = spectator;
+                            connection.seat = remote_seats
+                                .iter()
+                                .copied()
+                                .find(|seat| !spectator && !taken.contains(seat));
+                            events.push(Event::Joined(connection.seat));
+                        }
+                        let seat = connection.seat;
+                        connection.link.send(&state(game, seat));
+                    }
+                    Message::Move { ply, action } => {
+                        let connection = &mut self.connections[i];
+                        let valid = connection.seat == Some(game.turn())
+                            && ply == game.ply()
+                            && is_legal(game, action)
+                            && !events.iter().any(|e| matches!(e, Event::Move(_)));
+                        if valid {
+                            events.push(Event::Move(action));
+                        } else {
+                            // The client is out of step, so start it again
+                            let seat = connection.seat;
+                            connection.link.send(&state(game, seat));
+                        }
+                    }
+                    // Only the host sends the game
+                    Message::State { .. } => {}
+                }
+            }
+            i += 1;
+        }
+
+        events
+    }
+
+    /// Tells every client about the move just played.
+    pub fn moved(&mut self, game: &Game) {
+        if let Some(message) = last_move(game) {
+            for connection in self.connections.iter_mut().filter(|c| c.greeted) {
+                connection.link.send(&message);
+            }
+        }
+    }
+
+    /// Sends the whole game to every client, after it was changed other than
+    /// by a move.
+    pub fn sync(&mut self, game: &Game) {
+        for connection in self.connections.iter_mut().filter(|c| c.greeted) {
+            connection.link.send(&state(game, connection.seat));
+        }
+    }
+}
+
+/// The joining side, which follows the host's game.
+pub struct Client {
+    address: SocketAddr,
+    link: Option<Link>,
+    last_attempt: Instant,
+    spectator: bool,
+    seat: Option<Turn>,
+}
+
+impl Client {
+    /// Connects to the host at `address` to play. Once connected, the
+    /// connection is made again whenever it drops.
+    pub fn connect(address: &str) -> io::Result<Self> {
+        Client::start(address, false)
+    }
+
+    /// Connects to the host at `address` only to watch.
+    pub fn watch(address: &str) -> io::Result<Self> {
+        Client::start(address, true)
+    }
+
+    fn start(address: &str, spectator: bool) -> io::Result<Self> {
+        let address = resolve(address)?;
+        let mut client = Client {
+            address,
+            link: None,
+            last_attempt: Instant::now(),
+            spectator,
+            seat: None,
+        };
+        client.link = Some(client.open()?);
+        Ok(client)
+    }
+
+    fn open(&self) -> io::Result<Link> {
+        let mut link = Link::new(TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT)?)?;
+        link.send(&Message::Hello {
+            spectator: self.spectator,
+        });
+        Ok(link)
+    }
+
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// The side this client plays, once the host has said
+    pub fn seat(&self) -> Option<Turn> {
+        self.seat
+    }
+
+    /// Reconnects if needed and handles the host's messages.
+    pub fn poll(&mut self, game: &Game) -> Vec<Event> {
+        let mut events = vec![];
+
+        if self.link.is_none() && self.last_attempt.elapsed() >= RECONNECT_INTERVAL {
+            self.last_attempt = Instant::now();
+            if let Ok(link) = self.open() {
+                self.link = Some(link);
+                events.push(Event::Connected);
+            }
+        }
+        let Some(link) = &mut self.link else {
+            return events;
+        };
+
+        let messages = match link.poll() {
+            Ok(messages) => messages,
+            Err(_) => {
+                self.link = None;
+                self.last_attempt = Instant::now();
+                events.push(Event::Disconnected);
+                return events;
+            }
+        };
+
+        // Moves are checked against a copy of the game, since the front end
+        // only makes them once they have all been read
+        if messages.is_empty() {
+            return events;
+        }
+        let mut following = game.clone();
+        for message in messages {
+            match message {
+                Message::State {
+                    rows,
+                    cols,
+                    x_to_win,
+                    players,
+                    rules,
+                    blocked,
+                    start,
+                    moves,
+                    seat,
+                } => {
+                    // A host never sends a game that can't be played
+                    let Some(game) = setup(
+                        rows,
+                        cols,
+                        x_to_win,
+                        players,
+                        rules,
+                        &blocked,
+                        start.as_deref(),
+                    )
+                    .and_then(|game| replay(game, &moves)) else {
+                        continue;
+                    };
+                    self.seat = seat;
+                    following = game.clone();
+                    events.retain(|e| !matches!(e, Event::Move(_)));
+                    events.push(Event::Sync {
+                        game: Box::new(game),
+                        seat,
+                    });
+                }
+                Message::Move { ply, action }
+                    if ply == following.ply() && is_legal(&following, action) =>
+                {
+                    following.play(action);
+                    events.push(Event::Move(action));
+                }
+                // Our own move coming back
+                Message::Move { ply, action }
+                    if ply < following.ply() && following.history()[ply].action == action => {}
+                // Out of step, so ask for the whole game again
+                Message::Move { .. } => link.send(&Message::Hello {
+                    spectator: self.spectator,
+                }),
+                Message::Hello { .. } => {}
+            }
+        }
+
+        events
+    }
+
+    /// Tells the host about the move just played on this side. Spectators
+    /// have nothing to tell.
+    pub fn moved(&mut self, game: &Game) {
+        if self.spectator {
+            return;
+        }
+        if let (Some(link), Some(message)) = (&mut self.link, last_move(game)) {
+            link.send(&message);
+        }
+    }
+}
+
+/// Either end of a network game
+pub enum Peer {
+    Host(Host),
+    Client(Client),
+}
+
+impl Peer {
+    pub fn poll(&mut self, game: &Game, remote_seats: &[Turn]) -> Vec<Event> {
+        match self {
+            Peer::Host(host) => host.poll(game, remote_seats),
+            Peer::Client(client) => client.poll(game),
+        }
+    }
+
+    /// Passes on the move just played on this side.
+    pub fn moved(&mut self, game: &Game) {
+        match self {
+            Peer::Host(host) => host.moved(game),
+            Peer::Client(client) => client.moved(game),
+        }
+    }
+
+    /// Passes a move from one client on to the others, if this side is the
+    /// host.
+    pub fn relay(&mut self, game: &Game) {
+        if let Peer::Host(host) = self {
+            host.moved(game);
+        }
+    }
+
+    /// Sends the whole game to the other side, if this side is the host.
+    pub fn sync(&mut self, game: &Game) {
+        if let Peer::Host(host) = self {
+            host.sync(game);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    /// Calls `f` until it gives something, failing the test if that takes
+    /// too long
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        let started = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "timed out waiting on the other end"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn new_game() -> Game {
+        let mut game = Game::new(6, 7);
+        game.verify(6, 7, 4);
+        game
+    }
+
+    /// Sends what `client` has waiting without reading anything, which
+    /// otherwise only happens when it is polled
+    fn flush_client(client: &mut Client) {
+        if let Some(link) = &mut client.link {
+            let _ = link.flush();
+        }
+    }
+
+    /// Sends what `host` has waiting for its clients without reading anything
+    fn flush_host(host: &mut Host) {
+        for connection in &mut host.connections {
+            let _ = connection.link.flush();
+        }
+    }
+
+    /// A host on a free port of localhost and the address to reach it
+    fn host() -> (Host, String) {
+        let host = Host::bind("127.0.0.1:0").unwrap();
+        let address = host.local_addr().unwrap().to_string();
+        (host, address)
+    }
+
+    /// Polls `host` until one of its events matches `wanted`, passing on
+    /// anything `client` has to send
+    fn host_event(
+        host: &mut Host,
+        game: &Game,
+        remote_seats: &[Turn],
+        client: &mut Client,
+        wanted: fn(&Event) -> bool,
+    ) -> Event {
+        wait_for(|| {
+            flush_client(client);
+            host.poll(game, remote_seats).into_iter().find(wanted)
+        })
+    }
+
+    /// Polls `client` until one of its events matches `wanted`, passing on
+    /// anything `host` has to send
+    fn client_event(
+        client: &mut Client,
+        game: &Game,
+        host: &mut Host,
+        wanted: fn(&Event) -> bool,
+    ) -> Event {
+        wait_for(|| {
+            flush_host(host);
+            client.poll(game).into_iter().find(wanted)
+        })
+    }
+
+    #[test]
+    fn host_and_client_play_moves() {
+        let (mut host, address) = host();
+        let mut host_game = new_game();
+        let seats = [Turn::Player2];
+
+        let mut client = Client::connect(&address).unwrap();
+        let joined = host_event(&mut host, &host_game, &seats, &mut client, |e| {
+            matches!(e, Event::Joined(_))
+        });
+        assert!(matches!(joined, Event::Joined(Some(Turn::Player2))));
+
+        let Event::Sync { game, seat } = client_event(&mut client, &new_game(), &mut host, |e| {
+            matches!(e, Event::Sync { .. })
+        }) else {
+            unreachable!()
+        };
+        let mut client_game = *game;
+        assert_eq!(seat, Some(Turn::Player2));
+        assert_eq!(client.seat(), Some(Turn::Player2));
+
+        // The host's move reaches the client
+        host_game.play(Action::Drop(3));
+        host.moved(&host_game);
+        let event = client_event(&mut client, &client_game, &mut host, |e| {
+            matches!(e, Event::Move(_))
+        });
+        assert!(matches!(event, Event::Move(Action::Drop(3))));
+        client_game.play(Action::Drop(3));
+
+        // And the client's reply reaches the host
+        client_game.play(Action::Drop(4));
+        client.moved(&client_game);
+        let event = host_event(&mut host, &host_game, &seats, &mut client, |e| {
+            matches!(e, Event::Move(_))
+        });
+        assert!(matches!(event, Event::Move(Action::Drop(4))));
+        host_game.play(Action::Drop(4));
+        assert_eq!(host_game.history(), client_game.history());
+    }
+
+    #[test]
+    fn host_refuses_moves_out_of_turn() {
+        let (mut host, address) = host();
+        let host_game = new_game();
+        let seats = [Turn::Player2];
+
+        let mut client = Client::connect(&address).unwrap();
+        host_event(&mut host, &host_game, &seats, &mut client, |e| {
+            matches!(e, Event::Joined(_))
+        });
+        let Event::Sync { game, .. } = client_event(&mut client, &new_game(), &mut host, |e| {
+            matches!(e, Event::Sync { .. })
+        }) else {
+            unreachable!()
+        };
+
+        // Player 2 moving first is answered with the whole game again
+        let mut client_game = *game;
+        client_game.play(Action::Drop(0));
+        client.moved(&client_game);
+        let game = wait_for(|| {
+            flush_client(&mut client);
+            assert!(host.poll(&host_game, &seats).is_empty());
+            client.poll(&client_game).into_iter().find_map(|e| match e {
+                Event::Sync { game, .. } => Some(game),
+                _ => None,
+            })
+        });
+        assert_eq!(game.ply(), 0);
+    }
+
+    #[test]
+    fn spectators_catch_up_and_follow() {
+        let (mut host, address) = host();
+        let mut host_game = new_game();
+        host_game.play(Action::Drop(2));
+
+        let mut spectator = Client::watch(&address).unwrap();
+        let joined = host_event(
+            &mut host,
+            &host_game,
+            &[Turn::Player2],
+            &mut spectator,
+            |e| matches!(e, Event::Joined(_)),
+        );
+        assert!(matches!(joined, Event::Joined(None)));
+        assert_eq!(host.spectators(), 1);
+
+        let Event::Sync { game, seat } =
+            client_event(&mut spectator, &new_game(), &mut host, |e| {
+                matches!(e, Event::Sync { .. })
+            })
+        else {
+            unreachable!()
+        };
+        assert_eq!(seat, None);
+        assert_eq!(game.history(), host_game.history());
+
+        host_game.play(Action::Drop(5));
+        host.moved(&host_game);
+        let event = client_event(&mut spectator, &game, &mut host, |e| {
+            matches!(e, Event::Move(_))
+        });
+        assert!(matches!(event, Event::Move(Action::Drop(5))));
+    }
+
+    #[test]
+    fn spectators_never_get_a_seat_or_a_move() {
+        let (mut host, address) = host();
+        let mut host_game = new_game();
+        host_game.play(Action::Drop(3));
+        let seats = [Turn::Player2];
+
+        // The seat is free but a spectator doesn't take it
+        let mut spectator = Client::watch(&address).unwrap();
+        host_event(&mut host, &host_game, &seats, &mut spectator, |e| {
+            matches!(e, Event::Joined(None))
+        });
+        let Event::Sync { game, .. } = client_event(&mut spectator, &new_game(), &mut host, |e| {
+            matches!(e, Event::Sync { .. })
+        }) else {
+            unreachable!()
+        };
+        assert!(spectator.is_spectator());
+        assert_eq!(spectator.seat(), None);
+
+        let mut player = Client::connect(&address).unwrap();
+        host_event(&mut host, &host_game, &seats, &mut player, |e| {
+            matches!(e, Event::Joined(Some(Turn::Player2)))
+        });
+        assert_eq!(host.seated(), [Turn::Player2]);
+        assert_eq!(host.spectators(), 1);
+
+        // Spectators say nothing about moves on their side, and a move sent
+        // anyway is answered with the game again
+        let mut spectator_game = *game;
+        spectator_game.play(Action::Drop(0));
+        spectator.moved(&spectator_game);
+        let link = spectator.link.as_mut().unwrap();
+        link.send(&Message::Move {
+            ply: 1,
+            action: Action::Drop(0),
+        });
+        let game = wait_for(|| {
+            flush_client(&mut spectator);
+            assert!(host.poll(&host_game, &seats).is_empty());
+            spectator
+                .poll(&spectator_game)
+                .into_iter()
+                .find_map(|e| match e {
+                    Event::Sync { game, .. } => Some(game),
+                    _ => None,
+                })
+        });
+        assert_eq!(game.history(), host_game.history());
+
+        drop(spectator);
+        host_event(&mut host, &host_game, &seats, &mut player, |e| {
+            matches!(e, Event::Left(None))
+        });
+        assert_eq!(host.spectators(), 0);
+    }
+
+    #[test]
+    fn client_reconnects_after_the_host_drops_it() {
+        let (mut host, address) = host();
+        let host_game = new_game();
+        let seats = [Turn::Player2];
+
+        let mut client = Client::connect(&address).unwrap();
+        host_event(&mut host, &host_game, &seats, &mut client, |e| {
+            matches!(e, Event::Joined(_))
+        });
+        client_event(&mut client, &new_game(), &mut host, |e| {
+            matches!(e, Event::Sync { .. })
+        });
+
+        host.connections.clear();
+        client_event(&mut client, &new_game(), &mut host, |e| {
+            matches!(e, Event::Disconnected)
+        });
+        assert!(!client.is_connected());
+
+        // The next attempt is made after the reconnect interval
+        client.last_attempt -= RECONNECT_INTERVAL;
+        client_event(&mut client, &new_game(), &mut host, |e| {
+            matches!(e, Event::Connected)
+        });
+        host_event(&mut host, &host_game, &seats, &mut client, |e| {
+            matches!(e, Event::Joined(Some(Turn::Player2)))
+        });
+        client_event(&mut client, &new_game(), &mut host, |e| {
+            matches!(e, Event::Sync { .. })
+        });
+    }
+
+    #[test]
+    fn endless_messages_drop_the_peer() {
+        let (mut host, address) = host();
+        let game = new_game();
+
+        let writer = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let chunk = [b'x'; 1 << 16];
+            // Stops once the host hangs up
+            while stream.write_all(&chunk).is_ok() {}
+        });
+        // The writer only stops once the host has hung up on it
+        wait_for(|| {
+            host.poll(&game, &[]);
+            writer.is_finished().then_some(())
+        });
+        writer.join().unwrap();
+        assert!(host.connections.is_empty());
+    }
+}
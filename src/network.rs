@@ -0,0 +1,180 @@
+//! Hosting a game for other instances to join, or joining or watching one.
+//! Connections can't be made from the web, so there the controls are left out
+//! and nothing ever arrives.
+
+use connect_x::{game::Game, Turn};
+
+/// Something the network did to the game
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub enum Change {
+    /// The other side played a move, which has been made on the game
+    Move,
+    /// The game was replaced by the host's, in which this side plays the seat
+    /// given
+    Sync(Option<Turn>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Network {
+    peer: Option<connect_x::net::Peer>,
+    host_address: String,
+    join_address: String,
+    status: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Network {
+    pub fn new() -> Self {
+        use connect_x::net::DEFAULT_PORT;
+
+        Network {
+            peer: None,
+            host_address: format!("0.0.0.0:{DEFAULT_PORT}"),
+            join_address: format!("127.0.0.1:{DEFAULT_PORT}"),
+            status: String::new(),
+        }
+    }
+
+    /// Whether this side follows a game hosted somewhere else, as a player or
+    /// a spectator
+    pub fn is_client(&self) -> bool {
+        matches!(self.peer, Some(connect_x::net::Peer::Client(_)))
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        use connect_x::net::{Client, Host, Peer};
+
+        ui.collapsing("Network", |ui| {
+            if self.peer.is_none() {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.host_address);
+                    if ui.button("Host").clicked() {
+                        self.status = match Host::bind(&self.host_address) {
+                            Ok(host) => {
+                                let address = host
+                                    .local_addr()
+                                    .map_or(self.host_address.clone(), |a| a.to_string());
+                                self.peer = Some(Peer::Host(host));
+                                format!("Hosting on {address}")
+                            }
+                            Err(err) => format!("Could not host: {err}"),
+                        };
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.join_address);
+                    let join = ui.button("Join").clicked();
+                    let watch = ui.button("Watch").clicked();
+                    if join || watch {
+                        let connected = if watch {
+                            Client::watch(&self.join_address)
+                        } else {
+                            Client::connect(&self.join_address)
+                        };
+                        self.status = match connected {
+                            Ok(client) => {
+                                self.peer = Some(Peer::Client(client));
+                                format!("Connected to {}", self.join_address)
+                            }
+                            Err(err) => format!("Could not connect: {err}"),
+                        };
+                    }
+                });
+            } else if ui.button("Disconnect").clicked() {
+                self.peer = None;
+                self.status = "Disconnected".to_owned();
+            }
+
+            if let Some(Peer::Host(host)) = &self.peer {
+                ui.label(format!("Spectators: {}", host.spectators()));
+            }
+
+            if !self.status.is_empty() {
+                ui.label(&self.status);
+            }
+        });
+    }
+
+    /// Handles whatever has arrived, playing the other side's moves on `game`
+    /// or replacing it with the host's. `remote_seats` are the sides that
+    /// clients play when hosting.
+    pub fn poll(&mut self, game: &mut Game, remote_seats: &[Turn]) -> Vec<Change> {
+        use connect_x::net::Event;
+
+        let Some(peer) = &mut self.peer else {
+            return vec![];
+        };
+
+        let mut changes = vec![];
+        for event in peer.poll(game, remote_seats) {
+            match event {
+                Event::Joined(Some(seat)) => {
+                    self.status = format!("Someone joined, playing {seat}");
+                }
+                Event::Joined(None) => self.status = "Someone is watching".to_owned(),
+                Event::Left(Some(seat)) => {
+                    self.status = format!("{seat} left");
+                }
+                Event::Left(None) => self.status = "A spectator left".to_owned(),
+                Event::Connected => self.status = "Reconnected".to_owned(),
+                Event::Disconnected => {
+                    self.status = "Lost the connection, reconnecting…".to_owned()
+                }
+                Event::Sync { game: hosted, seat } => {
+                    *game = *hosted;
+                    self.status = match seat {
+                        Some(seat) => format!("Playing {seat}"),
+                        None => "Watching".to_owned(),
+                    };
+                    changes.push(Change::Sync(seat));
+                }
+                Event::Move(col) => {
+                    game.play(col);
+                    // Let everyone else hear about it too
+                    peer.relay(game);
+                    changes.push(Change::Move);
+                }
+            }
+        }
+        changes
+    }
+
+    /// Passes on a move just played on this side.
+    pub fn moved(&mut self, game: &Game) {
+        if let Some(peer) = &mut self.peer {
+            peer.moved(game);
+        }
+    }
+
+    /// Sends the whole game to the clients after it was changed other than by
+    /// a move.
+    pub fn sync(&mut self, game: &Game) {
+        if let Some(peer) = &mut self.peer {
+            peer.sync(game);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct Network;
+
+#[cfg(target_arch = "wasm32")]
+impl Network {
+    pub fn new() -> Self {
+        Network
+    }
+
+    pub fn is_client(&self) -> bool {
+        false
+    }
+
+    pub fn ui(&mut self, _ui: &mut egui::Ui) {}
+
+    pub fn poll(&mut self, _game: &mut Game, _remote_seats: &[Turn]) -> Vec<Change> {
+        vec![]
+    }
+
+    pub fn moved(&mut self, _game: &Game) {}
+
+    pub fn sync(&mut self, _game: &Game) {}
+}
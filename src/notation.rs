@@ -0,0 +1,694 @@
+//! Text formats for writing down games and positions.
+//!
+//! A game is written as the columns that were played, numbered from 1. On
+//! boards with at most 9 columns each move is a single digit (`4453`), wider
+//! boards separate the moves with commas (`12,3,15`). Under the PopOut rules a
+//! pop is written as its column after a `p` (`445p4`). Without gravity a move
+//! is written as its row from the top and its column (`r6c4,r1c1`), always
+//! separated by commas.
+//!
+//! A position is written in three space separated fields, similar to FEN in
+//! chess:
+//!
+//! ```text
+//! 6x7x4 7/7/7/7/3y3/3r3 r
+//! ```
+//!
+//! 1. The rows, columns and pieces in a row needed to win, followed by the
+//!    number of players when there are more than two (`6x7x4x3`).
+//! 2. The rows from top to bottom, separated by `/`. `r` is a piece of player
+//!    one, `y` a piece of player two and a number is that many empty cells.
+//!    Players three to eight are `g`, `b`, `p`, `o`, `c` and `m`, and `#` is a
+//!    blocked cell.
+//! 3. Whose turn it is, written as that player's piece.
+
+use std::{error::Error, fmt};
+
+use crate::{
+    board::{Action, Board, GameState, Pieces, Rules},
+    game::Game,
+    Turn, MAX_COL, MAX_PLAYERS, MAX_ROW, MIN_PLAYERS,
+};
+
+/// Boards wider than this separate moves with commas
+const MAX_DIGIT_COLS: usize = 9;
+
+/// The letter for each player's pieces, in turn order
+const PIECE_CHARS: [char; MAX_PLAYERS] = ['r', 'y', 'g', 'b', 'p', 'o', 'c', 'm'];
+
+const BLOCKED_CHAR: char = '#';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationError {
+    /// The position string ended before this field
+    MissingField(&'static str),
+    /// There was more text after the last field
+    TrailingInput(String),
+    /// The size field isn't `<rows>x<cols>x<x_to_win>[x<players>]` within the
+    /// board limits
+    InvalidSize(String),
+    /// The cell at this position in the board field isn't a number, the
+    /// piece of a player in the game or a blocked cell
+    InvalidCell {
+        row: usize,
+        text: String,
+    },
+    WrongRowCount {
+        expected: usize,
+        found: usize,
+    },
+    WrongRowLength {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A piece has an empty cell below it
+    FloatingPiece {
+        row: usize,
+        col: usize,
+    },
+    /// The side to move field isn't the piece of a player in the game
+    InvalidTurn(String),
+    /// The move at this index of a move list isn't a column number, with or
+    /// without a `p` in front, or a cell such as `r3c4`
+    InvalidMove {
+        index: usize,
+        text: String,
+    },
+    /// The move at this index of a move list is not on the board
+    ColumnOutOfRange {
+        index: usize,
+        col: usize,
+    },
+    /// The move at this index of a move list is into a full column
+    ColumnFull {
+        index: usize,
+        col: usize,
+    },
+    /// The move at this index of a move list pops a piece that isn't the
+    /// player's own, or pops when the rules don't allow it
+    CantPop {
+        index: usize,
+        col: usize,
+    },
+    /// The move at this index of a move list is into a cell that is taken, or
+    /// places a piece when there is gravity
+    CantPut {
+        index: usize,
+        row: usize,
+        col: usize,
+    },
+    /// The move at this index of a move list comes after the game has ended
+    GameOver {
+        index: usize,
+    },
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::MissingField(field) => write!(f, "missing the {field} field"),
+            NotationError::TrailingInput(text) => {
+                write!(f, "unexpected text after the position: '{text}'")
+            }
+            NotationError::InvalidSize(text) => write!(
+                f,
+                "'{text}' is not a valid size, expected <rows>x<cols>x<x> with at most \
+                 {MAX_ROW} rows, {MAX_COL} cols and x no larger than either, optionally \
+                 followed by x<players> for {MIN_PLAYERS} to {MAX_PLAYERS} players"
+            ),
+            NotationError::InvalidCell { row, text } => {
+                write!(
+                    f,
+                    "'{text}' in row {} is not a player's piece, a blocked cell or a number",
+                    row + 1
+                )
+            }
+            NotationError::WrongRowCount { expected, found } => {
+                write!(f, "expected {expected} rows but found {found}")
+            }
+            NotationError::WrongRowLength {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} has {found} cells but the board has {expected} columns",
+                row + 1
+            ),
+            NotationError::FloatingPiece { row, col } => write!(
+                f,
+                "the piece in row {}, column {} has nothing below it",
+                row + 1,
+                col + 1
+            ),
+            NotationError::InvalidTurn(text) => {
+                write!(
+                    f,
+                    "'{text}' is not a side to move, expected a player's piece"
+                )
+            }
+            NotationError::InvalidMove { index, text } => {
+                write!(f, "move {} '{text}' is not a column or a cell", index + 1)
+            }
+            NotationError::ColumnOutOfRange { index, col } => {
+                write!(
+                    f,
+                    "move {} is to column {col}, which is off the board",
+                    index + 1
+                )
+            }
+            NotationError::ColumnFull { index, col } => {
+                write!(f, "move {} is to column {col}, which is full", index + 1)
+            }
+            NotationError::CantPop { index, col } => write!(
+                f,
+                "move {} pops column {col}, which the player can't pop from",
+                index + 1
+            ),
+            NotationError::CantPut { index, row, col } => write!(
+                f,
+                "move {} is to row {row}, column {col}, which can't be played",
+                index + 1
+            ),
+            NotationError::GameOver { index } => {
+                write!(f, "move {} comes after the game has ended", index + 1)
+            }
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+/// Writes a single move with its column, and row if it has one, numbered from 1.
+pub fn format_action(action: Action) -> String {
+    match action {
+        Action::Drop(col) => (col + 1).to_string(),
+        Action::Pop(col) => format!("p{}", col + 1),
+        Action::Put(row, col) => format!("r{}c{}", row + 1, col + 1),
+    }
+}
+
+/// Reads a single move written by [`format_action`], without checking it is
+/// on the board.
+pub fn parse_action(text: &str) -> Option<Action> {
+    if let Some(cell) = text.strip_prefix('r') {
+        let (row, col) = cell.split_once('c')?;
+        return match (row.parse::<usize>(), col.parse::<usize>()) {
+            (Ok(row), Ok(col)) if row > 0 && col > 0 => Some(Action::Put(row - 1, col - 1)),
+            _ => None,
+        };
+    }
+
+    let (pop, number) = match text.strip_prefix('p') {
+        Some(number) => (true, number),
+        None => (false, text),
+    };
+    match number.parse::<usize>() {
+        Ok(col) if col > 0 && pop => Some(Action::Pop(col - 1)),
+        Ok(col) if col > 0 => Some(Action::Drop(col - 1)),
+        _ => None,
+    }
+}
+
+/// Writes moves as a move list for a board with `cols` columns.
+pub fn format_moves(cols: usize, moves: impl IntoIterator<Item = Action>) -> String {
+    let moves: Vec<Action> = moves.into_iter().collect();
+    let puts = moves.iter().any(|action| matches!(action, Action::Put(..)));
+    let moves = moves.into_iter().map(format_action);
+    if cols <= MAX_DIGIT_COLS && !puts {
+        moves.collect()
+    } else {
+        moves.collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Reads a move list for a board with `cols` columns.
+pub fn parse_moves(cols: usize, text: &str) -> Result<Vec<Action>, NotationError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let tokens: Vec<&str> = if cols <= MAX_DIGIT_COLS && !text.contains([',', 'r']) {
+        // A pop takes its `p` along with the digit after it
+        let mut tokens = vec![];
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            if c != 'p' {
+                tokens.push(&text[start..i + c.len_utf8()]);
+                start = i + c.len_utf8();
+            }
+        }
+        if start < text.len() {
+            tokens.push(&text[start..]);
+        }
+        tokens
+    } else {
+        text.split(',').map(str::trim).collect()
+    };
+
+    tokens
+        .into_iter()
+        .enumerate()
+        .map(|(index, token)| {
+            let action = parse_action(token).ok_or_else(|| NotationError::InvalidMove {
+                index,
+                text: token.to_owned(),
+            })?;
+            if action.col() >= cols {
+                return Err(NotationError::ColumnOutOfRange {
+                    index,
+                    col: action.col() + 1,
+                });
+            }
+            Ok(action)
+        })
+        .collect()
+}
+
+fn turn_char(turn: &Turn) -> char {
+    PIECE_CHARS[turn.index()]
+}
+
+/// The player whose piece is written as `c`, if they are in a game of `players`
+fn char_turn(c: char, players: usize) -> Option<Turn> {
+    PIECE_CHARS[..players]
+        .iter()
+        .position(|&p| p == c)
+        .map(Turn::from_index)
+}
+
+impl Board {
+    /// Writes the position with `turn` to move.
+    pub fn to_notation(&self, turn: &Turn) -> String {
+        let mut rows = vec![];
+        for row in 0..self.rows() {
+            let mut text = String::new();
+            let mut empty = 0;
+            for col in 0..self.cols() {
+                let piece = match self.piece_at(row, col) {
+                    Pieces::Player(turn) => turn_char(&turn),
+                    Pieces::Blocked => BLOCKED_CHAR,
+                    Pieces::Empty => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+                if empty > 0 {
+                    text.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                text.push(piece);
+            }
+            if empty > 0 {
+                text.push_str(&empty.to_string());
+            }
+            rows.push(text);
+        }
+
+        let mut size = format!("{}x{}x{}", self.rows(), self.cols(), self.x_to_win());
+        if self.players() != 2 {
+            size.push_str(&format!("x{}", self.players()));
+        }
+        format!("{size} {} {}", rows.join("/"), turn_char(turn))
+    }
+
+    /// Reads a position, returning the board and whose turn it is.
+    pub fn from_notation(text: &str) -> Result<(Board, Turn), NotationError> {
+        Board::from_notation_with(text, Rules::default())
+    }
+
+    /// Reads a position to be played under `rules`, where without gravity
+    /// pieces may float.
+    pub fn from_notation_with(text: &str, rules: Rules) -> Result<(Board, Turn), NotationError> {
+        let mut fields = text.split_whitespace();
+        let size = fields.next().ok_or(NotationError::MissingField("size"))?;
+        let cells = fields.next().ok_or(NotationError::MissingField("board"))?;
+        let side = fields
+            .next()
+            .ok_or(NotationError::MissingField("side to move"))?;
+        if let Some(extra) = fields.next() {
+            return Err(NotationError::TrailingInput(extra.to_owned()));
+        }
+
+        let (rows, cols, x_to_win, players) = parse_size(size)?;
+        let mut board = Board::with_players(rows, cols, players);
+        board.verify(rows, cols, x_to_win);
+        board.set_rules(rules);
+
+        let row_texts: Vec<&str> = cells.split('/').collect();
+        if row_texts.len() != rows {
+            return Err(NotationError::WrongRowCount {
+                expected: rows,
+                found: row_texts.len(),
+            });
+        }
+
+        // Set all at once, as checking the board after every cell is slow on
+        // big boards
+        let mut filled = vec![];
+        for (row, row_text) in row_texts.into_iter().enumerate() {
+            let mut col = 0;
+            let mut chars = row_text.char_indices().peekable();
+            while let Some((start, c)) = chars.next() {
+                let piece = match c {
+                    '0'..='9' => {
+                        let mut end = start + 1;
+                        while let Some(&(i, '0'..='9')) = chars.peek() {
+                            end = i + 1;
+                            chars.next();
+                        }
+                        let run = &row_text[start..end];
+                        match run.parse::<usize>() {
+                            Ok(n) if n > 0 && n <= cols - col => col += n,
+                            // Stopping here keeps a huge count from overflowing
+                            Ok(n) if n > 0 => {
+                                return Err(NotationError::WrongRowLength {
+                                    row,
+                                    expected: cols,
+                                    found: col.saturating_add(n),
+                                })
+                            }
+                            _ => {
+                                return Err(NotationError::InvalidCell {
+                                    row,
+                                    text: run.to_owned(),
+                                })
+                            }
+                        }
+                        continue;
+                    }
+                    BLOCKED_CHAR => Pieces::Blocked,
+                    _ => match char_turn(c, players) {
+                        Some(turn) => Pieces::Player(turn),
+                        None => {
+                            return Err(NotationError::InvalidCell {
+                                row,
+                                text: c.to_string(),
+                            })
+                        }
+                    },
+                };
+                if col == cols {
+                    return Err(NotationError::WrongRowLength {
+                        row,
+                        expected: cols,
+                        found: col + 1,
+                    });
+                }
+                filled.push((row, col, piece));
+                col += 1;
+            }
+            if col != cols {
+                return Err(NotationError::WrongRowLength {
+                    row,
+                    expected: cols,
+                    found: col,
+                });
+            }
+        }
+        board.set_cells(&filled);
+
+        if rules.gravity {
+            if let Some((row, col)) = board.floating_piece() {
+                return Err(NotationError::FloatingPiece { row, col });
+            }
+        }
+
+        let mut side_chars = side.chars();
+        let turn = match (side_chars.next(), side_chars.next()) {
+            (Some(c), None) => char_turn(c, players),
+            _ => None,
+        }
+        .ok_or_else(|| NotationError::InvalidTurn(side.to_owned()))?;
+
+        Ok((board, turn))
+    }
+}
+
+/// Reads the rows, columns, pieces in a row to win and players of a size
+/// field, where the players are 2 unless given.
+pub(crate) fn parse_size(text: &str) -> Result<(usize, usize, usize, usize), NotationError> {
+    let invalid = || NotationError::InvalidSize(text.to_owned());
+
+    let parts = text
+        .split('x')
+        .map(|part| part.parse::<usize>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (rows, cols, x_to_win, players) = match parts[..] {
+        [rows, cols, x_to_win] => (rows, cols, x_to_win, 2),
+        [rows, cols, x_to_win, players] => (rows, cols, x_to_win, players),
+        _ => return Err(invalid()),
+    };
+
+    if !(1..=MAX_ROW).contains(&rows)
+        || !(1..=MAX_COL).contains(&cols)
+        || !(1..=rows.min(cols)).contains(&x_to_win)
+        || !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players)
+    {
+        return Err(invalid());
+    }
+
+    Ok((rows, cols, x_to_win, players))
+}
+
+impl Game {
+    /// Writes the moves played so far as a move list.
+    pub fn to_move_notation(&self) -> String {
+        format_moves(self.board().cols(), self.history().iter().map(|m| m.action))
+    }
+
+    /// Plays every move of a move list, stopping at the first one that can't
+    /// be played.
+    pub fn play_notation(&mut self, text: &str) -> Result<(), NotationError> {
+        for (index, action) in parse_moves(self.board().cols(), text)?
+            .into_iter()
+            .enumerate()
+        {
+            if self.board().game_state() != GameState::OnGoing {
+                return Err(NotationError::GameOver { index });
+            }
+            if !self.play(action) {
+                let col = action.col() + 1;
+                return Err(match action {
+                    Action::Drop(_) => NotationError::ColumnFull { index, col },
+                    Action::Pop(_) => NotationError::CantPop { index, col },
+                    Action::Put(row, _) => NotationError::CantPut {
+                        index,
+                        row: row + 1,
+                        col,
+                    },
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Why `text` can't be read as a position, if it can't
+    fn error(text: &str) -> Option<NotationError> {
+        Board::from_notation(text).err()
+    }
+
+    #[test]
+    fn positions_read_back() {
+        let text = "6x7x4 7/7/7/7/3y3/2#r3 r";
+        let (board, turn) = Board::from_notation(text).unwrap();
+        assert_eq!(board.to_notation(&turn), text);
+        assert_eq!(board.piece_at(5, 2), Pieces::Blocked);
+
+        let text = "4x4x3x3 4/4/4/rygr y";
+        let (board, turn) = Board::from_notation(text).unwrap();
+        assert_eq!(board.players(), 3);
+        assert_eq!(board.to_notation(&turn), text);
+    }
+
+    #[test]
+    fn huge_empty_counts_are_rejected() {
+        for text in [
+            "1x2x1 18446744073709551615r r",
+            "1x2x1 r18446744073709551615 r",
+            "1x2x1 1184467440737095516151 r",
+            "1x2x1 99999999999999999999999 r",
+        ] {
+            assert!(
+                matches!(
+                    error(text),
+                    Some(NotationError::WrongRowLength { .. } | NotationError::InvalidCell { .. })
+                ),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn rows_of_the_wrong_length_are_rejected() {
+        let too_long = NotationError::WrongRowLength {
+            row: 0,
+            expected: 3,
+            found: 4,
+        };
+        assert_eq!(error("2x3x2 4/3 r"), Some(too_long.clone()));
+        assert_eq!(error("2x3x2 2yr/3 r"), Some(too_long.clone()));
+        assert_eq!(error("2x3x2 ryry/3 r"), Some(too_long));
+        assert_eq!(
+            error("2x3x2 3/ry r"),
+            Some(NotationError::WrongRowLength {
+                row: 1,
+                expected: 3,
+                found: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_positions_are_rejected() {
+        assert_eq!(error(""), Some(NotationError::MissingField("size")));
+        assert_eq!(error("6x7x4"), Some(NotationError::MissingField("board")));
+        assert_eq!(
+            error("2x2x2 2/2"),
+            Some(NotationError::MissingField("side to move"))
+        );
+        assert_eq!(
+            error("2x2x2 2/2 r y"),
+            Some(NotationError::TrailingInput("y".to_owned()))
+        );
+        for size in [
+            "2x2", "0x2x1", "2x2x3", "501x2x1", "2x2x2x9", "2xax2", "-1x2x1",
+        ] {
+            assert_eq!(
+                error(&format!("{size} 2/2 r")),
+                Some(NotationError::InvalidSize(size.to_owned()))
+            );
+        }
+        assert_eq!(
+            error("2x2x2 2/2/2 r"),
+            Some(NotationError::WrongRowCount {
+                expected: 2,
+                found: 3,
+            })
+        );
+        assert_eq!(
+            error("2x2x2 2/0r r"),
+            Some(NotationError::InvalidCell {
+                row: 1,
+                text: "0".to_owned(),
+            })
+        );
+        // Player three's piece in a two player game
+        assert_eq!(
+            error("2x2x2 2/gr r"),
+            Some(NotationError::InvalidCell {
+                row: 1,
+                text: "g".to_owned(),
+            })
+        );
+        assert_eq!(
+            error("2x2x2 r1/2 y"),
+            Some(NotationError::FloatingPiece { row: 0, col: 0 })
+        );
+        for side in ["x", "ry", "g"] {
+            assert_eq!(
+                error(&format!("2x2x2 2/2 {side}")),
+                Some(NotationError::InvalidTurn(side.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn floating_pieces_are_allowed_without_gravity() {
+        let rules = Rules {
+            gravity: false,
+            ..Rules::default()
+        };
+        assert!(Board::from_notation_with("2x2x2 r1/2 y", rules).is_ok());
+    }
+
+    #[test]
+    fn malformed_move_lists_are_rejected() {
+        assert_eq!(
+            parse_moves(7, "4x"),
+            Err(NotationError::InvalidMove {
+                index: 1,
+                text: "x".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_moves(7, "48"),
+            Err(NotationError::ColumnOutOfRange { index: 1, col: 8 })
+        );
+        assert_eq!(
+            parse_moves(12, "1,,2"),
+            Err(NotationError::InvalidMove {
+                index: 1,
+                text: String::new(),
+            })
+        );
+        assert_eq!(
+            parse_moves(12, "18446744073709551616"),
+            Err(NotationError::InvalidMove {
+                index: 0,
+                text: "18446744073709551616".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_moves(7, "0"),
+            Err(NotationError::InvalidMove {
+                index: 0,
+                text: "0".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn big_boards_full_of_blocked_cells_read_quickly() {
+        use std::time::{Duration, Instant};
+
+        use crate::{MAX_COL, MAX_ROW};
+
+        // Every other cell is blocked, in a checkerboard
+        let rows: Vec<String> = (0..MAX_ROW)
+            .map(|row| if row % 2 == 0 { "#1" } else { "1#" }.repeat(MAX_COL / 2))
+            .collect();
+        let text = format!("{MAX_ROW}x{MAX_COL}x4 {} r", rows.join("/"));
+
+        let started = Instant::now();
+        let (board, turn) = Board::from_notation(&text).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(board.to_notation(&turn), text);
+
+        // The same as blocking the cells of an empty board
+        let blocked = board.blocked_cells();
+        assert_eq!(blocked.len(), MAX_ROW * MAX_COL / 2);
+        let mut shaped = Board::new(MAX_ROW, MAX_COL);
+        shaped.verify(MAX_ROW, MAX_COL, 4);
+        shaped.set_shape(&blocked);
+        assert_eq!(board.hash(), shaped.hash());
+        assert_eq!(board.moves(&turn), shaped.moves(&turn));
+        assert_eq!(board.game_state(), GameState::OnGoing);
+    }
+
+    #[test]
+    fn pieces_read_in_bulk_keep_the_board_consistent() {
+        let (mut board, turn) = Board::from_notation("6x7x4 7/7/7/1r5/y#5/r2#3 y").unwrap();
+        assert_eq!((board.floor(0), board.floor(1), board.floor(3)), (6, 4, 5));
+        assert_eq!(board.piece_count(), 3);
+        assert_eq!(board.game_state(), GameState::OnGoing);
+
+        // Moves land on top of what was read
+        assert!(board.place(0, &turn));
+        assert_eq!(board.piece_at(3, 0), Pieces::Player(Turn::Player2));
+        assert!(board.place(1, &Turn::Player1));
+        assert_eq!(board.piece_at(2, 1), Pieces::Player(Turn::Player1));
+        assert!(board.place(3, &Turn::Player2));
+        assert_eq!(board.piece_at(4, 3), Pieces::Player(Turn::Player2));
+
+        let (board, _) = Board::from_notation("6x7x4 7/7/7/7/yyy4/rrrr3 y").unwrap();
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+    }
+}
@@ -0,0 +1,553 @@
+//! A line based text protocol for playing through other programs, in the
+//! spirit of UCI in chess.
+//!
+//! The controller writes commands to the engine's standard input, one per
+//! line, and reads its replies from standard output:
+//!
+//! ```text
+//! > cxi                       Start talking the protocol
+//! < id name <name>            Optional, the engine's name
+//! < cxiok                     The engine is ready for commands
+//! > isready                   Asks the engine to finish what it is doing
+//! < readyok
+//! > newgame 6 7 4             Rows, columns and pieces in a row to win
+//! > position 4,4,5,3          The columns played so far, numbered from 1
+//! > position 4453             Or the same moves as a move list
+//! > position fen 6x7x4 7/7/7/7/3y3/3r3 r
+//!                             Or the position to play from
+//! > go movetime 1000          Pick a move in about this many milliseconds
+//! < bestmove 4                The chosen column, numbered from 1
+//! > quit
+//! ```
+//!
+//! Move lists are written as in [`crate::notation`], so the moves of a game
+//! can be passed on as they are written down. Moves may also be separated by
+//! spaces instead of commas.
+//!
+//! `newgame 6 7 4 3` starts a game for three players, who move in turn
+//! order. Without the fourth number there are two.
+//!
+//! `newgame 6 7 4 popout` starts a game under the PopOut rules, where a move
+//! can also be a pop written as its column after a `p`, such as `bestmove p4`.
+//! `newgame 6 7 4 nogravity` starts a game without gravity, where every move
+//! is a cell written as its row from the top and its column, such as
+//! `bestmove r2c5`. `hwrap` and `vwrap` make lines carry on around the left
+//! and right or the top and bottom edges of the board.
+//!
+//! Engines may also send `info <text>` lines at any time, which are ignored,
+//! and should ignore any command they don't understand.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::{
+    board::{Action, Board, Rules},
+    notation::{format_action, parse_action, parse_moves},
+    Turn,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Cxi,
+    IsReady,
+    NewGame {
+        rows: usize,
+        cols: usize,
+        x_to_win: usize,
+        players: usize,
+        rules: Rules,
+    },
+    /// The moves made since the start of the game, as a move list that is read
+    /// once the width of the board is known
+    Moves(String),
+    /// A position in the notation of [`Board::to_notation`]
+    Fen(String),
+    Go {
+        movetime: u64,
+    },
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Id(String),
+    CxiOk,
+    ReadyOk,
+    BestMove(Action),
+    Info(String),
+}
+
+/// A line that isn't part of the protocol, or whose arguments don't make sense
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolError(pub String);
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not understood", self.0)
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Cxi => write!(f, "cxi"),
+            Command::IsReady => write!(f, "isready"),
+            Command::NewGame {
+                rows,
+                cols,
+                x_to_win,
+                players,
+                rules,
+            } => {
+                write!(f, "newgame {rows} {cols} {x_to_win}")?;
+                // Left out for two players, so older engines still follow
+                if *players != 2 {
+                    write!(f, " {players}")?;
+                }
+                if rules.popout {
+                    write!(f, " popout")?;
+                }
+                if !rules.gravity {
+                    write!(f, " nogravity")?;
+                }
+                if rules.wrap_horizontal {
+                    write!(f, " hwrap")?;
+                }
+                if rules.wrap_vertical {
+                    write!(f, " vwrap")?;
+                }
+                Ok(())
+            }
+            Command::Moves(moves) => write!(f, "position {moves}"),
+            Command::Fen(position) => write!(f, "position fen {position}"),
+            Command::Go { movetime } => write!(f, "go movetime {movetime}"),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let error = || ProtocolError(line.to_owned());
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("cxi") => Ok(Command::Cxi),
+            Some("isready") => Ok(Command::IsReady),
+            Some("quit") => Ok(Command::Quit),
+            Some("newgame") => {
+                let mut words: Vec<&str> = words.collect();
+                let mut rules = Rules::default();
+                while let Some(&word) = words.last() {
+                    match word {
+                        "popout" => rules.popout = true,
+                        "nogravity" => rules.gravity = false,
+                        "hwrap" => rules.wrap_horizontal = true,
+                        "vwrap" => rules.wrap_vertical = true,
+                        _ => break,
+                    }
+                    words.pop();
+                }
+                match words
+                    .into_iter()
+                    .map(|w| w.parse().map_err(|_| error()))
+                    .collect::<Result<Vec<usize>, _>>()?[..]
+                {
+                    [rows, cols, x_to_win] => Ok(Command::NewGame {
+                        rows,
+                        cols,
+                        x_to_win,
+                        players: 2,
+                        rules,
+                    }),
+                    [rows, cols, x_to_win, players] => Ok(Command::NewGame {
+                        rows,
+                        cols,
+                        x_to_win,
+                        players,
+                        rules,
+                    }),
+                    _ => Err(error()),
+                }
+            }
+            Some("position") => match words.next() {
+                Some("fen") => Ok(Command::Fen(words.collect::<Vec<_>>().join(" "))),
+                first => Ok(Command::Moves(
+                    first
+                        .into_iter()
+                        .chain(words)
+                        .flat_map(|word| word.split(','))
+                        .filter(|m| !m.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                )),
+            },
+            Some("go") => match (words.next(), words.next().map(str::parse)) {
+                (Some("movetime"), Some(Ok(movetime))) => Ok(Command::Go { movetime }),
+                _ => Err(error()),
+            },
+            _ => Err(error()),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Id(name) => write!(f, "id name {name}"),
+            Response::CxiOk => write!(f, "cxiok"),
+            Response::ReadyOk => write!(f, "readyok"),
+            Response::BestMove(action) => write!(f, "bestmove {}", format_action(*action)),
+            Response::Info(text) => write!(f, "info {text}"),
+        }
+    }
+}
+
+impl FromStr for Response {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let error = || ProtocolError(line.to_owned());
+        let line = line.trim();
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        match word {
+            "cxiok" => Ok(Response::CxiOk),
+            "readyok" => Ok(Response::ReadyOk),
+            "info" => Ok(Response::Info(rest.to_owned())),
+            "id" => match rest.split_once(' ') {
+                Some(("name", name)) => Ok(Response::Id(name.to_owned())),
+                _ => Err(error()),
+            },
+            "bestmove" => parse_action(rest.trim())
+                .map(Response::BestMove)
+                .ok_or_else(error),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// The game an engine has been told about
+#[derive(Debug, Clone)]
+pub struct EngineState {
+    pub board: Board,
+    pub turn: Turn,
+}
+
+impl EngineState {
+    pub fn new(rows: usize, cols: usize, x_to_win: usize, players: usize, rules: Rules) -> Self {
+        let mut board = Board::with_players(rows, cols, players);
+        board.verify(rows, cols, x_to_win);
+        board.set_rules(rules);
+        EngineState {
+            board,
+            turn: Turn::Player1,
+        }
+    }
+
+    /// Sets up the position of a `position` command, leaving the state alone
+    /// if it isn't valid.
+    pub fn set_position(&mut self, command: &Command) -> Result<(), Box<dyn Error>> {
+        match command {
+            Command::Moves(moves) => {
+                let mut board = self.board.clone();
+                board.reset();
+                let mut turn = Turn::Player1;
+                for action in parse_moves(board.cols(), moves)? {
+                    if action.col() >= board.cols() || !board.play(action, &turn) {
+                        return Err(format!("{} can't be played", format_action(action)).into());
+                    }
+                    turn = turn.next(board.players());
+                }
+                self.board = board;
+                self.turn = turn;
+            }
+            Command::Fen(position) => {
+                (self.board, self.turn) = Board::from_notation_with(position, self.board.rules())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use server::serve;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod server {
+    use std::{
+        error::Error,
+        io::{BufRead, Write},
+        sync::atomic::AtomicBool,
+    };
+
+    use super::{Command, EngineState, Response};
+    use crate::{
+        agent::{random_turn, system_clock, Agent, SearchLimits, TurnSearch},
+        board::Rules,
+        mcts::MctsLimit,
+        transposition::TranspositionTable,
+        MAX_COL, MAX_PLAYERS, MAX_ROW, MIN_PLAYERS,
+    };
+
+    /// The agent with its time limit replaced by `seconds`
+    fn with_think_time(agent: &Agent, seconds: f64) -> Agent {
+        match agent {
+            Agent::AlphaBeta(limits) => Agent::AlphaBeta(SearchLimits {
+                think_time: seconds,
+                ..*limits
+            }),
+            Agent::Solver(limits) => Agent::Solver(SearchLimits {
+                think_time: seconds,
+                ..*limits
+            }),
+            Agent::Mcts(MctsLimit::Time(_)) => Agent::Mcts(MctsLimit::Time(seconds)),
+            other => other.clone(),
+        }
+    }
+
+    /// Plays as `agent` over the protocol, reading commands from `input` and
+    /// writing replies to `output` until `quit` or the end of the input.
+    pub fn serve(
+        agent: &Agent,
+        table_mb: usize,
+        input: impl BufRead,
+        mut output: impl Write,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut table = TranspositionTable::new(table_mb);
+        let mut state = EngineState::new(6, 7, 4, 2, Rules::default());
+        let mut reply = |response: Response| -> std::io::Result<()> {
+            writeln!(output, "{response}")?;
+            output.flush()
+        };
+
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let command = match line.parse::<Command>() {
+                Ok(command) => command,
+                Err(err) => {
+                    reply(Response::Info(format!("error {err}")))?;
+                    continue;
+                }
+            };
+
+            match command {
+                Command::Cxi => {
+                    reply(Response::Id(format!("Connect X {}", agent.name())))?;
+                    reply(Response::CxiOk)?;
+                }
+                Command::IsReady => reply(Response::ReadyOk)?,
+                Command::NewGame {
+                    rows,
+                    cols,
+                    x_to_win,
+                    players,
+                    rules,
+                } => {
+                    if !(1..=MAX_ROW).contains(&rows)
+                        || !(1..=MAX_COL).contains(&cols)
+                        || !(1..=rows.min(cols)).contains(&x_to_win)
+                        || !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players)
+                    {
+                        reply(Response::Info(format!("error '{line}' is not a board")))?;
+                        continue;
+                    }
+                    state = EngineState::new(rows, cols, x_to_win, players, rules);
+                    table.clear();
+                }
+                Command::Moves(_) | Command::Fen(_) => {
+                    if let Err(err) = state.set_position(&command) {
+                        reply(Response::Info(format!("error {err}")))?;
+                    }
+                }
+                Command::Go { movetime } => {
+                    let agent = with_think_time(agent, movetime as f64 / 1000.0);
+                    let action = match agent {
+                        // There's nobody to ask, so play something legal
+                        Agent::Player | Agent::Remote => random_turn(&state.board, &state.turn),
+                        _ => {
+                            let mut search =
+                                TurnSearch::new(&agent, &state.board, &state.turn, system_clock);
+                            let action = search
+                                .step(&mut table, f64::INFINITY, &AtomicBool::new(false))
+                                .flatten();
+                            if let Some(error) = search.error() {
+                                reply(Response::Info(format!("error {error}")))?;
+                            }
+                            action
+                        }
+                    };
+                    match action {
+                        Some(action) => reply(Response::BestMove(action))?,
+                        None => reply(Response::Info("error no moves to make".to_owned()))?,
+                    }
+                }
+                Command::Quit => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::Agent, game::Game, MAX_PLAYERS};
+
+    /// The position an engine is left in by `line` on a new 6x7x4 board
+    fn position_after(line: &str) -> Result<String, Box<dyn Error>> {
+        let mut state = EngineState::new(6, 7, 4, 2, Rules::default());
+        state.set_position(&line.parse()?)?;
+        Ok(state.board.to_notation(&state.turn))
+    }
+
+    #[test]
+    fn move_lists_read_as_written_down() {
+        let expected = "6x7x4 7/7/7/3y3/3y3/3rr2 r";
+        for line in [
+            "position 4,4,5,4",
+            "position 4 4 5 4",
+            "position 4454",
+            "position 4, 4,5 ,4",
+        ] {
+            assert_eq!(position_after(line).unwrap(), expected, "{line}");
+        }
+
+        let mut game = Game::new(6, 7);
+        game.verify(6, 7, 4);
+        game.play_notation("4454").unwrap();
+        let line = format!("position {}", game.to_move_notation());
+        assert_eq!(position_after(&line).unwrap(), expected);
+    }
+
+    #[test]
+    fn bad_move_lists_leave_the_position_alone() {
+        let mut state = EngineState::new(6, 7, 4, 2, Rules::default());
+        state.set_position(&"position 44".parse().unwrap()).unwrap();
+        for line in [
+            "position 48",
+            "position 4x",
+            "position p4",
+            "position 4444444",
+        ] {
+            assert!(
+                state.set_position(&line.parse().unwrap()).is_err(),
+                "{line}"
+            );
+            assert_eq!(
+                state.board.to_notation(&state.turn),
+                "6x7x4 7/7/7/7/3y3/3r3 r"
+            );
+        }
+    }
+
+    #[test]
+    fn commands_read_back() {
+        for command in [
+            Command::Cxi,
+            Command::NewGame {
+                rows: 6,
+                cols: 7,
+                x_to_win: 4,
+                players: 2,
+                rules: Rules {
+                    popout: true,
+                    wrap_horizontal: true,
+                    ..Rules::default()
+                },
+            },
+            Command::NewGame {
+                rows: 5,
+                cols: 5,
+                x_to_win: 3,
+                players: 3,
+                rules: Rules::default(),
+            },
+            Command::Moves("4,4,p4".to_owned()),
+            Command::Fen("6x7x4 7/7/7/7/7/3r3 y".to_owned()),
+            Command::Go { movetime: 250 },
+            Command::Quit,
+        ] {
+            assert_eq!(command.to_string().parse(), Ok(command));
+        }
+    }
+
+    #[test]
+    fn new_games_are_for_two_players_unless_told() {
+        let Ok(Command::NewGame { players, .. }) = "newgame 6 7 4 popout".parse() else {
+            panic!("not a new game");
+        };
+        assert_eq!(players, 2);
+        let Ok(Command::NewGame { players, rules, .. }) = "newgame 6 7 4 3 hwrap".parse() else {
+            panic!("not a new game");
+        };
+        assert_eq!(players, 3);
+        assert!(rules.wrap_horizontal);
+
+        let command = Command::NewGame {
+            rows: 6,
+            cols: 7,
+            x_to_win: 4,
+            players: 2,
+            rules: Rules::default(),
+        };
+        assert_eq!(command.to_string(), "newgame 6 7 4");
+    }
+
+    #[test]
+    fn move_lists_go_round_every_player() {
+        let mut state = EngineState::new(5, 5, 3, 3, Rules::default());
+        state
+            .set_position(&"position 1,2,3,1".parse().unwrap())
+            .unwrap();
+        assert_eq!(state.turn, Turn::Player2);
+        assert_eq!(
+            state.board.to_notation(&state.turn),
+            "5x5x3x3 5/5/5/r4/ryg2 y"
+        );
+    }
+
+    /// The replies of an engine playing randomly to `input`
+    fn serve_lines(input: &str) -> Vec<String> {
+        let mut output = vec![];
+        serve(&Agent::Random, 1, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn engines_play_for_the_player_to_move() {
+        let replies = serve_lines("newgame 5 5 3 3\nposition 1,2\ngo movetime 10\nquit\n");
+        assert_eq!(replies.len(), 1);
+        let Ok(Response::BestMove(action)) = replies[0].parse() else {
+            panic!("{replies:?}");
+        };
+
+        let mut state = EngineState::new(5, 5, 3, 3, Rules::default());
+        state
+            .set_position(&"position 1,2".parse().unwrap())
+            .unwrap();
+        assert_eq!(state.turn, Turn::Player3);
+        assert!(state.board.play(action, &state.turn));
+    }
+
+    #[test]
+    fn player_counts_are_checked() {
+        for players in [0, 1, MAX_PLAYERS + 1] {
+            let replies = serve_lines(&format!("newgame 6 7 4 {players}\n"));
+            assert_eq!(replies.len(), 1);
+            assert!(replies[0].starts_with("info error"), "{replies:?}");
+        }
+        assert!(serve_lines(&format!("newgame 6 7 4 {MAX_PLAYERS}\n")).is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+use connect_x::{
+    board::{Action, Board, Pieces, WinningLine},
+    Turn, MAX_PLAYERS,
+};
+use macroquad::prelude::*;
+
+use crate::LEFT_BUFFER;
+
+/// The colour of each player's pieces until they are changed in the settings
+pub const DEFAULT_COLORS: [[f32; 3]; MAX_PLAYERS] = [
+    [0.90, 0.16, 0.22],
+    [0.99, 0.98, 0.00],
+    [0.13, 0.70, 0.29],
+    [0.16, 0.38, 0.88],
+    [0.58, 0.25, 0.80],
+    [0.98, 0.55, 0.10],
+    [0.10, 0.80, 0.85],
+    [0.90, 0.35, 0.70],
+];
+
+fn player_color(colors: &[[f32; 3]], turn: Turn, alpha: f32) -> Color {
+    let [r, g, b] = colors[turn.index()];
+    Color::new(r, g, b, alpha)
+}
+
+/// The (row, col) of the cell under `psn`, if there is one.
+pub fn cell_at(board: &Board, piece_size: f32, psn: (f32, f32)) -> Option<(usize, usize)> {
+    let (x, y) = (psn.0 - LEFT_BUFFER, psn.1);
+    if x < 0.0
+        || y < 0.0
+        || x >= board.cols() as f32 * piece_size
+        || y >= board.rows() as f32 * piece_size
+    {
+        return None;
+    }
+    Some(((y / piece_size) as usize, (x / piece_size) as usize))
+}
+
+pub fn mouse_hover(
+    board: &Board,
+    piece_size: f32,
+    psn: (f32, f32),
+    turn: &Turn,
+    colors: &[[f32; 3]],
+) -> Option<(usize, usize)> {
+    let x = psn.0 - LEFT_BUFFER;
+    let height = board.rows() as f32 * piece_size;
+    if x < 0.0 || x >= board.cols() as f32 * piece_size {
+        return None;
+    }
+
+    let col = (x / piece_size) as usize;
+    let x_pos = LEFT_BUFFER + col as f32 * piece_size;
+    let color = player_color(colors, *turn, 0.5);
+
+    // Without gravity the piece goes in the cell itself rather than the column
+    if !board.rules().gravity {
+        let (row, col) = cell_at(board, piece_size, psn)?;
+        draw_rectangle(
+            x_pos,
+            row as f32 * piece_size,
+            piece_size,
+            piece_size,
+            color,
+        );
+        return Some((row, col));
+    }
+
+    draw_rectangle(x_pos, 0.0, piece_size, height, color);
+    // Show that the bottom piece can be popped
+    if board.can_pop(col, turn) {
+        let y = (board.floor(col) as f32 - 0.5) * piece_size;
+        let x = x_pos + piece_size / 2.0;
+        draw_circle_lines(x, y, piece_size / 2.5, piece_size / 15.0, BLACK);
+    }
+
+    Some((0, col))
+}
+
+pub fn draw_board(board: &Board, piece_size: f32, colors: &[[f32; 3]]) {
+    for i in 0..board.rows() {
+        for j in 0..board.cols() {
+            let mut x = LEFT_BUFFER + j as f32 * piece_size;
+            let mut y = i as f32 * piece_size;
+            // Blocked cells are solid, with no hole for a piece
+            let piece = board.piece_at(i, j);
+            let background = if piece == Pieces::Blocked {
+                DARKGRAY
+            } else {
+                GRAY
+            };
+            draw_rectangle(x, y, piece_size - 1.0, piece_size - 1.0, background);
+
+            x += piece_size / 2.0;
+            y += piece_size / 2.0;
+            let color = match piece {
+                Pieces::Player(turn) => player_color(colors, turn, 1.0),
+                Pieces::Empty => WHITE,
+                Pieces::Blocked => continue,
+            };
+            draw_circle(x, y, piece_size / 2.5, color);
+        }
+    }
+}
+
+/// Rings the cells of each winning line with a pulsing outline and joins
+/// their centres, leaving a gap where a line goes around the edge.
+pub fn draw_winning_lines(lines: &[WinningLine], piece_size: f32) {
+    let pulse = (get_time() * 4.0).sin() as f32 * 0.5 + 0.5;
+    let thickness = piece_size / 20.0 * (1.0 + pulse);
+    let centre = |(row, col): (usize, usize)| {
+        (
+            LEFT_BUFFER + (col as f32 + 0.5) * piece_size,
+            (row as f32 + 0.5) * piece_size,
+        )
+    };
+
+    for line in lines {
+        for cell in line.cells() {
+            let (x, y) = centre(cell);
+            draw_circle_lines(x, y, piece_size / 2.5, thickness, BLACK);
+        }
+        let cells: Vec<(usize, usize)> = line.cells().collect();
+        for pair in cells.windows(2) {
+            let ((r1, c1), (r2, c2)) = (pair[0], pair[1]);
+            if r1.abs_diff(r2) > 1 || c1.abs_diff(c2) > 1 {
+                continue;
+            }
+            let (x1, y1) = centre(pair[0]);
+            let (x2, y2) = centre(pair[1]);
+            draw_line(x1, y1, x2, y2, thickness, BLACK);
+        }
+    }
+}
+
+pub fn player_turn(
+    board: &Board,
+    turn: &Turn,
+    piece_size: f32,
+    colors: &[[f32; 3]],
+) -> Option<Action> {
+    let mouse_psn = mouse_position();
+
+    let (row, col) = mouse_hover(board, piece_size, mouse_psn, turn, colors)?;
+
+    // Left click drops and right click pops
+    if is_mouse_button_pressed(MouseButton::Left) && !board.rules().gravity {
+        Some(Action::Put(row, col))
+    } else if is_mouse_button_pressed(MouseButton::Left) {
+        Some(Action::Drop(col))
+    } else if is_mouse_button_pressed(MouseButton::Right) && board.rules().popout {
+        Some(Action::Pop(col))
+    } else {
+        None
+    }
+}
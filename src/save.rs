@@ -0,0 +1,538 @@
+//! Saving games as versioned JSON.
+//!
+//! A save holds the settings the game was started with and the moves played,
+//! and is loaded by replaying the moves on a fresh board. Every move is
+//! checked along the way, so a save that was edited by hand can't produce a
+//! position the rules don't allow.
+//!
+//! Saves from older versions are upgraded as they are read: version 1 only had
+//! two players, version 2 only had moves that drop a piece, version 3 only had
+//! the PopOut rules to choose, version 4 had no blocked cells and version 5
+//! always started from an empty board.
+
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    agent::Agent,
+    board::{Board, GameState, Rules},
+    editor::check_position,
+    game::{Game, Move},
+    Turn, MAX_COL, MAX_PLAYERS, MAX_ROW, MIN_PLAYERS,
+};
+
+/// Bumped whenever the layout of [`SaveFile`] changes, along with a step in
+/// `UPGRADES` from the version before
+pub const SAVE_VERSION: u32 = 6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub rows: usize,
+    pub cols: usize,
+    pub x_val: usize,
+    pub players: usize,
+    pub rules: Rules,
+    /// The (row, col) of each blocked cell, which older saves didn't have
+    #[serde(default)]
+    pub blocked: Vec<(usize, usize)>,
+    /// The position the moves were played from, in the notation of
+    /// [`Board::to_notation`](crate::board::Board::to_notation), if the game
+    /// didn't start from an empty board
+    #[serde(default)]
+    pub start: Option<String>,
+    /// The agent for each player, in turn order
+    pub agents: Vec<Agent>,
+    pub sleep_time: f64,
+    pub moves: Vec<Move>,
+    pub turn: Turn,
+    pub result: GameState,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The file was written by a version of the game this one can't read
+    UnsupportedVersion(u32),
+    Json(serde_json::Error),
+    /// The dimensions, `x_val`, number of players or a blocked cell are
+    /// outside the limits
+    InvalidSize,
+    /// The starting position can't be read, doesn't fit the saved settings or
+    /// couldn't come up in a game
+    InvalidStart,
+    /// The move at this index can't be played
+    InvalidMove(usize),
+    /// The saved turn or result doesn't match the replayed moves
+    Mismatch(&'static str),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::UnsupportedVersion(version) => write!(
+                f,
+                "save version {version} is not supported, expected {SAVE_VERSION}"
+            ),
+            SaveError::Json(err) => write!(f, "invalid save file: {err}"),
+            SaveError::InvalidSize => {
+                write!(
+                    f,
+                    "the saved board size, blocked cells or number of players are out of range"
+                )
+            }
+            SaveError::InvalidStart => write!(f, "the saved starting position is not valid"),
+            SaveError::InvalidMove(index) => {
+                write!(f, "move {} in the save can't be played", index + 1)
+            }
+            SaveError::Mismatch(field) => {
+                write!(f, "the saved {field} doesn't match the moves played")
+            }
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaveError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        SaveError::Json(err)
+    }
+}
+
+impl SaveFile {
+    /// Saves `game` along with the agents of the players in it, the first
+    /// [`Board::players`](crate::board::Board::players) of `agents`.
+    pub fn new(game: &Game, agents: &[Agent], sleep_time: f64) -> Self {
+        let board = game.board();
+        SaveFile {
+            version: SAVE_VERSION,
+            rows: board.rows(),
+            cols: board.cols(),
+            x_val: board.x_to_win(),
+            players: board.players(),
+            rules: board.rules(),
+            blocked: board.blocked_cells(),
+            start: game.start(),
+            agents: agents[..board.players()].to_vec(),
+            sleep_time,
+            moves: game.history().to_vec(),
+            turn: game.turn(),
+            result: board.game_state(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a save is always valid JSON")
+    }
+
+    /// Reads a save, checking the version before anything else so that newer
+    /// layouts give a clear error instead of a missing field.
+    pub fn from_json(text: &str) -> Result<Self, SaveError> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        let Version { version } = serde_json::from_str(text)?;
+        match version {
+            SAVE_VERSION => Ok(serde_json::from_str(text)?),
+            1.. if version < SAVE_VERSION => {
+                let save = UPGRADES[version as usize - 1..]
+                    .iter()
+                    .fold(serde_json::from_str(text)?, |save, upgrade| upgrade(save));
+                Ok(serde_json::from_value(save)?)
+            }
+            _ => Err(SaveError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Replays the saved moves into a new game.
+    pub fn to_game(&self) -> Result<Game, SaveError> {
+        if !(1..=MAX_ROW).contains(&self.rows)
+            || !(1..=MAX_COL).contains(&self.cols)
+            || !(1..=self.rows.min(self.cols)).contains(&self.x_val)
+            || !(MIN_PLAYERS..=MAX_PLAYERS).contains(&self.players)
+            || self.agents.len() != self.players
+            || self
+                .blocked
+                .iter()
+                .any(|&(row, col)| row >= self.rows || col >= self.cols)
+        {
+            return Err(SaveError::InvalidSize);
+        }
+
+        let mut game = Game::new(self.rows, self.cols);
+        game.verify(self.rows, self.cols, self.x_val);
+        game.set_players(self.players);
+        game.set_rules(self.rules);
+        game.set_shape(&self.blocked);
+        if let Some(start) = &self.start {
+            let (board, turn) = Board::from_notation_with(start, self.rules)
+                .map_err(|_| SaveError::InvalidStart)?;
+            if board.rows() != self.rows
+                || board.cols() != self.cols
+                || board.x_to_win() != self.x_val
+                || board.players() != self.players
+                || board.blocked_cells() != game.board().blocked_cells()
+                || check_position(&board, &turn).is_err()
+            {
+                return Err(SaveError::InvalidStart);
+            }
+            game.set_position(board, turn);
+        }
+        for (index, m) in self.moves.iter().enumerate() {
+            if m.action.col() >= self.cols
+                || m.turn != game.turn()
+                || game.board().game_state() != GameState::OnGoing
+                || !game.play(m.action)
+            {
+                return Err(SaveError::InvalidMove(index));
+            }
+        }
+
+        if game.turn() != self.turn {
+            return Err(SaveError::Mismatch("turn"));
+        }
+        if game.board().game_state() != self.result {
+            return Err(SaveError::Mismatch("result"));
+        }
+        Ok(game)
+    }
+}
+
+/// The steps that rewrite a save in the layout of the next version, starting
+/// from version 1
+const UPGRADES: [fn(Value) -> Value; SAVE_VERSION as usize - 1] =
+    [upgrade_v1, upgrade_v2, upgrade_v3, upgrade_v4, upgrade_v5];
+
+/// Rewrites a version 1 save in the layout of version 2. Anything missing is
+/// left for reading the save to complain about.
+fn upgrade_v1(mut save: Value) -> Value {
+    let Value::Object(fields) = &mut save else {
+        return save;
+    };
+
+    let player_one = fields.remove("player_one").unwrap_or(Value::Null);
+    let player_two = fields.remove("player_two").unwrap_or(Value::Null);
+    fields.insert("players".to_owned(), json!(2));
+    fields.insert("agents".to_owned(), json!([player_one, player_two]));
+    if let Some(result) = fields.get_mut("result") {
+        match result.as_str() {
+            Some("P1Win") => *result = json!({ "Win": "Player1" }),
+            Some("P2Win") => *result = json!({ "Win": "Player2" }),
+            _ => {}
+        }
+    }
+    fields.insert("version".to_owned(), json!(2));
+    save
+}
+
+/// Rewrites a version 2 save in the layout of version 3, where every move was
+/// a drop.
+fn upgrade_v2(mut save: Value) -> Value {
+    let Value::Object(fields) = &mut save else {
+        return save;
+    };
+
+    if let Some(Value::Array(moves)) = fields.get_mut("moves") {
+        for m in moves {
+            if let Value::Object(m) = m {
+                if let Some(col) = m.remove("col") {
+                    m.insert("action".to_owned(), json!({ "Drop": col }));
+                }
+            }
+        }
+    }
+    fields.insert("popout".to_owned(), json!(false));
+    fields.insert("version".to_owned(), json!(3));
+    save
+}
+
+/// Rewrites a version 3 save in the layout of version 4, where every game had
+/// gravity.
+fn upgrade_v3(mut save: Value) -> Value {
+    let Value::Object(fields) = &mut save else {
+        return save;
+    };
+
+    let popout = fields.remove("popout").unwrap_or(json!(false));
+    fields.insert(
+        "rules".to_owned(),
+        json!({ "gravity": true, "popout": popout }),
+    );
+    fields.insert("version".to_owned(), json!(4));
+    save
+}
+
+/// Rewrites a version 4 save in the layout of version 5, where no cells were
+/// blocked.
+fn upgrade_v4(mut save: Value) -> Value {
+    let Value::Object(fields) = &mut save else {
+        return save;
+    };
+
+    fields.insert("blocked".to_owned(), json!([]));
+    fields.insert("version".to_owned(), json!(5));
+    save
+}
+
+/// Rewrites a version 5 save in the layout of version 6, where every game
+/// started from an empty board.
+fn upgrade_v5(mut save: Value) -> Value {
+    let Value::Object(fields) = &mut save else {
+        return save;
+    };
+
+    fields.insert("start".to_owned(), Value::Null);
+    fields.insert("version".to_owned(), json!(6));
+    save
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `json` as a save and replays it, returning the position reached
+    fn load(json: &str) -> (SaveFile, String) {
+        let save = SaveFile::from_json(json).unwrap();
+        let game = save.to_game().unwrap();
+        let position = game.board().to_notation(&game.turn());
+        (save, position)
+    }
+
+    const V1: &str = r#"{
+        "version": 1,
+        "rows": 6,
+        "cols": 7,
+        "x_val": 4,
+        "player_one": "Player",
+        "player_two": "Random",
+        "sleep_time": 0.5,
+        "moves": [
+            { "col": 0, "turn": "Player1" },
+            { "col": 0, "turn": "Player2" },
+            { "col": 1, "turn": "Player1" },
+            { "col": 1, "turn": "Player2" },
+            { "col": 2, "turn": "Player1" },
+            { "col": 2, "turn": "Player2" },
+            { "col": 3, "turn": "Player1" }
+        ],
+        "turn": "Player2",
+        "result": "P1Win"
+    }"#;
+
+    const V2: &str = r#"{
+        "version": 2,
+        "rows": 5,
+        "cols": 5,
+        "x_val": 3,
+        "players": 3,
+        "agents": ["Player", "Random", "Player"],
+        "sleep_time": 0.0,
+        "moves": [
+            { "col": 0, "turn": "Player1" },
+            { "col": 1, "turn": "Player2" },
+            { "col": 2, "turn": "Player3" },
+            { "col": 0, "turn": "Player1" },
+            { "col": 1, "turn": "Player2" },
+            { "col": 2, "turn": "Player3" },
+            { "col": 0, "turn": "Player1" }
+        ],
+        "turn": "Player2",
+        "result": { "Win": "Player1" }
+    }"#;
+
+    const V3: &str = r#"{
+        "version": 3,
+        "rows": 6,
+        "cols": 7,
+        "x_val": 4,
+        "players": 2,
+        "popout": true,
+        "agents": ["Player", "Player"],
+        "sleep_time": 1.0,
+        "moves": [
+            { "action": { "Drop": 0 }, "turn": "Player1" },
+            { "action": { "Drop": 1 }, "turn": "Player2" },
+            { "action": { "Pop": 0 }, "turn": "Player1" }
+        ],
+        "turn": "Player2",
+        "result": "OnGoing"
+    }"#;
+
+    const V4: &str = r#"{
+        "version": 4,
+        "rows": 4,
+        "cols": 4,
+        "x_val": 3,
+        "players": 2,
+        "rules": { "gravity": false, "wrap_horizontal": true },
+        "agents": ["Player", "Random"],
+        "sleep_time": 0.5,
+        "moves": [
+            { "action": { "Put": [0, 3] }, "turn": "Player1" },
+            { "action": { "Put": [3, 3] }, "turn": "Player2" },
+            { "action": { "Put": [0, 0] }, "turn": "Player1" },
+            { "action": { "Put": [3, 2] }, "turn": "Player2" },
+            { "action": { "Put": [0, 1] }, "turn": "Player1" }
+        ],
+        "turn": "Player2",
+        "result": { "Win": "Player1" }
+    }"#;
+
+    const V5: &str = r#"{
+        "version": 5,
+        "rows": 6,
+        "cols": 7,
+        "x_val": 4,
+        "players": 2,
+        "rules": { "gravity": true },
+        "blocked": [[5, 1], [5, 3], [5, 5]],
+        "agents": ["Player", "Player"],
+        "sleep_time": 0.5,
+        "moves": [
+            { "action": { "Drop": 1 }, "turn": "Player1" },
+            { "action": { "Drop": 0 }, "turn": "Player2" }
+        ],
+        "turn": "Player1",
+        "result": "OnGoing"
+    }"#;
+
+    #[test]
+    fn version_1_loads() {
+        let (save, position) = load(V1);
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.players, 2);
+        assert_eq!(save.agents, [Agent::Player, Agent::Random]);
+        assert_eq!(save.rules, Rules::default());
+        assert_eq!(save.result, GameState::Win(Turn::Player1));
+        assert_eq!(position, "6x7x4 7/7/7/7/yyy4/rrrr3 y");
+    }
+
+    #[test]
+    fn version_2_loads() {
+        let (save, position) = load(V2);
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.rules, Rules::default());
+        assert_eq!(position, "5x5x3x3 5/5/r4/ryg2/ryg2 y");
+    }
+
+    #[test]
+    fn version_3_loads() {
+        let (save, position) = load(V3);
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(
+            save.rules,
+            Rules {
+                popout: true,
+                ..Rules::default()
+            }
+        );
+        assert_eq!(position, "6x7x4 7/7/7/7/7/1y5 y");
+    }
+
+    #[test]
+    fn version_4_loads() {
+        let (save, position) = load(V4);
+        assert_eq!(save.version, SAVE_VERSION);
+        assert!(!save.rules.gravity && save.rules.wrap_horizontal);
+        assert_eq!(save.result, GameState::Win(Turn::Player1));
+        assert_eq!(position, "4x4x3 rr1r/4/4/2yy y");
+    }
+
+    #[test]
+    fn version_5_loads() {
+        let (save, position) = load(V5);
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.start, None);
+        assert_eq!(position, "6x7x4 7/7/7/7/1r5/y#1#1#1 r");
+    }
+
+    #[test]
+    fn blocked_cells_are_saved() {
+        let mut game = Game::new(6, 7);
+        game.verify(6, 7, 4);
+        game.set_shape(&[(4, 1), (5, 1)]);
+        game.play_notation("122").unwrap();
+
+        let save = SaveFile::new(&game, &[Agent::Player, Agent::Player], 0.0);
+        let (copy, position) = load(&save.to_json());
+        assert_eq!(copy.version, SAVE_VERSION);
+        assert_eq!(copy.blocked, [(4, 1), (5, 1)]);
+        assert_eq!(position, "6x7x4 7/7/1r5/1y5/1#5/r#5 y");
+    }
+
+    #[test]
+    fn starting_positions_are_saved() {
+        let (board, turn) = Board::from_notation("6x7x4 7/7/7/7/3y3/2rr3 y").unwrap();
+        let mut game = Game::new(6, 7);
+        game.verify(6, 7, 4);
+        game.set_position(board, turn);
+        game.play_notation("4").unwrap();
+
+        let save = SaveFile::new(&game, &[Agent::Player, Agent::Random], 0.0);
+        let (copy, position) = load(&save.to_json());
+        assert_eq!(copy.start.as_deref(), Some("6x7x4 7/7/7/7/3y3/2rr3 y"));
+        assert_eq!(copy.moves.len(), 1);
+        assert_eq!(position, "6x7x4 7/7/7/3y3/3y3/2rr3 r");
+    }
+
+    #[test]
+    fn starting_positions_that_cant_come_up_are_rejected() {
+        let mut save = SaveFile::from_json(V5).unwrap();
+        save.moves.clear();
+        save.turn = Turn::Player1;
+        for start in [
+            "6x7x4 7/7/7/7/7/1#1#1#1 y",
+            "6x7x4 7/7/7/7/r6/1#1#1#1 y",
+            "6x7x4 7/7/7/7/7/r#r#1#1 y",
+            "6x7x4 7/7/7/7/7/7 r",
+            "5x7x4 7/7/7/7/1#1#1#1 r",
+            "6x7x4 7/7/7/7/7/1#1#1#1",
+        ] {
+            save.start = Some(start.to_owned());
+            assert!(
+                matches!(save.to_game(), Err(SaveError::InvalidStart)),
+                "{start}"
+            );
+        }
+
+        save.start = Some("6x7x4 7/7/7/7/7/r#1#1#y r".to_owned());
+        assert!(save.to_game().is_ok());
+    }
+
+    #[test]
+    fn saves_read_back() {
+        for json in [V1, V2, V3, V4, V5] {
+            let (save, position) = load(json);
+            let (copy, copy_position) = load(&save.to_json());
+            assert_eq!(copy, save);
+            assert_eq!(copy_position, position);
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let json = V4.replace("\"version\": 4", "\"version\": 99");
+        assert!(matches!(
+            SaveFile::from_json(&json),
+            Err(SaveError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn moves_that_cant_be_played_are_rejected() {
+        let json = V3.replace("{ \"Pop\": 0 }", "{ \"Pop\": 1 }");
+        let save = SaveFile::from_json(&json).unwrap();
+        assert!(matches!(save.to_game(), Err(SaveError::InvalidMove(2))));
+    }
+}
@@ -0,0 +1,72 @@
+//! Ready made board shapes, made by blocking off cells of a rectangular board.
+
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shape {
+    /// Every cell is open
+    #[default]
+    Plain,
+    /// A single cell blocked off low down in each outer third of the board.
+    /// With gravity nothing can get under a hole, so it also shortens its
+    /// column.
+    Holes,
+    /// The board narrows in steps towards the top
+    Pyramid,
+    /// Every other column has its bottom cell blocked off, so the columns
+    /// start at different heights
+    Staggered,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 4] = [Shape::Plain, Shape::Holes, Shape::Pyramid, Shape::Staggered];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Plain => "Plain",
+            Shape::Holes => "Holes",
+            Shape::Pyramid => "Pyramid",
+            Shape::Staggered => "Staggered",
+        }
+    }
+
+    /// The cells blocked off on a board of `rows` by `cols`.
+    pub fn blocked(&self, rows: usize, cols: usize) -> Vec<(usize, usize)> {
+        match self {
+            Shape::Plain => vec![],
+            Shape::Holes => {
+                // Second from the bottom, so only one cell is lost under each
+                let row = rows.saturating_sub(2);
+                let mut holes = vec![(row, cols / 4), (row, cols - 1 - cols / 4)];
+                holes.dedup();
+                holes
+            }
+            Shape::Pyramid => (0..rows)
+                .flat_map(|row| {
+                    let step = ((rows - 1 - row) / 2).min((cols - 1) / 2);
+                    (0..step)
+                        .chain(cols - step..cols)
+                        .map(move |col| (row, col))
+                })
+                .collect(),
+            Shape::Staggered => (1..cols).step_by(2).map(|col| (rows - 1, col)).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Shape {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Shape::ALL
+            .into_iter()
+            .find(|shape| shape.name().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
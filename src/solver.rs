@@ -0,0 +1,359 @@
+//! An exact solver for small boards.
+//!
+//! The solver runs a negamax search all the way to the end of the game, so it
+//! only finishes in reasonable time once few enough cells are left empty.
+//! Negamax relies on there being exactly two sides, so only two player games
+//! can be solved, and only under the standard rules where every move fills a
+//! cell.
+
+use std::{fmt, sync::atomic::AtomicBool};
+
+use crate::{
+    agent::{search_key, Clock, Stopper},
+    board::{Action, Board, Rules},
+    transposition::{Bound, Entry, TranspositionTable},
+    Turn,
+};
+
+/// Mixed into the table key so solved positions never mix with the
+/// heuristic values stored by the alpha-beta agent.
+const SOLVER_KEY: u64 = 0x6A09_E667_F3BC_C908;
+
+/// Boards with more empty cells than this are not worth trying to solve
+pub const MAX_SOLVE_CELLS: usize = 64;
+
+/// The game-theoretic value of a position for the player to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    /// The player to move wins with the given number of moves left to play,
+    /// counting both players
+    Win(usize),
+    /// The player to move loses with the given number of moves left to play
+    Loss(usize),
+    Draw,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Win(1) => write!(f, "win in 1 move"),
+            Value::Win(moves) => write!(f, "win in {moves} moves"),
+            Value::Loss(1) => write!(f, "loss in 1 move"),
+            Value::Loss(moves) => write!(f, "loss in {moves} moves"),
+            Value::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub value: Value,
+    /// A move that achieves `value`, `None` if the board is full
+    pub best_move: Option<Action>,
+}
+
+/// Whether the solver can work on `board` at all: a two player game under the
+/// standard rules, with no blocked cells and at most [`MAX_SOLVE_CELLS`] left
+/// empty.
+pub fn can_solve(board: &Board) -> bool {
+    let empty_cells = board.rows() * board.cols() - board.piece_count();
+    empty_cells <= MAX_SOLVE_CELLS
+        && board.players() == 2
+        && board.rules() == Rules::default()
+        && board.blocked_cells().is_empty()
+}
+
+/// Works out the exact value of the position for `turn` to move, giving up
+/// once `clock` reaches `stop_at`.
+///
+/// Returns `None` if the position can't be solved or time ran out first.
+pub fn solve(
+    board: &Board,
+    turn: &Turn,
+    table: &mut TranspositionTable,
+    clock: Clock,
+    stop_at: f64,
+) -> Option<Solution> {
+    if !can_solve(board) {
+        return None;
+    }
+
+    let cancel = AtomicBool::new(false);
+    let stopper = Stopper::new(clock, stop_at, &cancel);
+    Solver::new(table, stopper, board).solve(&mut board.clone(), turn)
+}
+
+/// Scores are relative to the side to move. A win is scored by how many cells
+/// were still empty just before the winning piece went in, so quicker wins
+/// score higher and the same position always gets the same score no matter
+/// how it was reached. A draw scores zero.
+pub(crate) struct Solver<'a> {
+    table: &'a mut TranspositionTable,
+    pub stopper: Stopper<'a>,
+    cells: i32,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(table: &'a mut TranspositionTable, stopper: Stopper<'a>, board: &Board) -> Self {
+        Solver {
+            table,
+            stopper,
+            cells: (board.rows() * board.cols()) as i32,
+        }
+    }
+
+    /// Finds the exact value of the position, or `None` if the search had to stop.
+    pub fn solve(&mut self, board: &mut Board, turn: &Turn) -> Option<Solution> {
+        let placed = board.piece_count() as i32;
+        let moves = board.moves(turn);
+
+        // Narrow down on the score with null window searches, which prune far
+        // more than a single search with a wide window
+        let mut min = -(self.cells - placed);
+        let mut max = self.cells - placed;
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+            let score = self.negamax(board, turn, med, med + 1, placed);
+            if self.stopper.aborted {
+                return None;
+            }
+            if score <= med {
+                max = score;
+            } else {
+                min = score;
+            }
+        }
+        let score = min;
+
+        // Find a move that keeps the score
+        let mut best_move = moves.first().copied();
+        for action in self.ordered_moves(board, turn, None) {
+            board.play(action, turn);
+            let child = if board.has_winner() {
+                self.cells - placed
+            } else {
+                -self.negamax(board, &turn.next(2), -score, -score + 1, placed + 1)
+            };
+            board.take_back(action, turn);
+            if self.stopper.aborted {
+                return None;
+            }
+            if child >= score {
+                best_move = Some(action);
+                break;
+            }
+        }
+
+        let value = match score {
+            0 => Value::Draw,
+            s if s > 0 => Value::Win((self.cells + 1 - s - placed) as usize),
+            s => Value::Loss((self.cells + 1 + s - placed) as usize),
+        };
+
+        Some(Solution { value, best_move })
+    }
+
+    /// Moves to try, starting with the one the table suggests and then
+    /// working out from the centre, where lines are most likely to be made.
+    fn ordered_moves(&self, board: &Board, turn: &Turn, first: Option<Action>) -> Vec<Action> {
+        let center = board.cols() as isize / 2;
+        let mut moves = board.moves(turn);
+        moves.sort_by_key(|action| ((action.col() as isize - center).abs(), action.col()));
+        if let Some(pos) = moves.iter().position(|&a| Some(a) == first) {
+            moves[..=pos].rotate_right(1);
+        }
+        moves
+    }
+
+    fn negamax(
+        &mut self,
+        board: &mut Board,
+        turn: &Turn,
+        mut alpha: i32,
+        mut beta: i32,
+        placed: i32,
+    ) -> i32 {
+        if self.stopper.tick() {
+            return 0;
+        }
+
+        let moves = board.moves(turn);
+        if moves.is_empty() {
+            return 0;
+        }
+
+        // Win straight away if possible
+        for &action in &moves {
+            board.play(action, turn);
+            let wins = board.has_winner();
+            board.take_back(action, turn);
+            if wins {
+                return self.cells - placed;
+            }
+        }
+
+        // The best that can happen now is winning with our next piece, and the
+        // worst is losing to the opponent's
+        let max = (self.cells - placed - 2).max(0);
+        let min = -(self.cells - placed - 1);
+        beta = beta.min(max);
+        alpha = alpha.max(min);
+        if alpha >= beta {
+            return alpha;
+        }
+
+        let key = search_key(board, turn, turn) ^ SOLVER_KEY;
+        let remaining = (self.cells - placed) as u32;
+        let (alpha_orig, beta_orig) = (alpha, beta);
+        let mut first = None;
+        if let Some(entry) = self.table.get(key) {
+            if entry.depth >= remaining {
+                match entry.bound {
+                    Bound::Exact => return entry.value,
+                    Bound::Lower => alpha = alpha.max(entry.value),
+                    Bound::Upper => beta = beta.min(entry.value),
+                }
+                if alpha >= beta {
+                    return entry.value;
+                }
+            }
+            first = entry.best_move;
+        }
+
+        let mut value = i32::MIN;
+        let mut best_move = None;
+        for action in self.ordered_moves(board, turn, first) {
+            board.play(action, turn);
+            let score = -self.negamax(board, &turn.next(2), -beta, -alpha, placed + 1);
+            board.take_back(action, turn);
+            if self.stopper.aborted {
+                return 0;
+            }
+            if score > value {
+                value = score;
+                best_move = Some(action);
+            }
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if value <= alpha_orig {
+            Bound::Upper
+        } else if value >= beta_orig {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.store(Entry {
+            key,
+            depth: remaining,
+            value,
+            bound,
+            best_move,
+        });
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{SearchLimits, TurnSearch},
+        board::GameState,
+    };
+
+    /// A clock that never moves, so the solver always finishes
+    fn frozen_clock() -> f64 {
+        0.0
+    }
+
+    fn solve_position(position: &str) -> Option<Solution> {
+        let (board, turn) = Board::from_notation(position).unwrap();
+        let mut table = TranspositionTable::new(1);
+        solve(&board, &turn, &mut table, frozen_clock, f64::INFINITY)
+    }
+
+    #[test]
+    fn finds_a_win_in_one() {
+        let solution = solve_position("6x7x4 7/7/7/7/yyy4/rrr4 r").unwrap();
+        assert_eq!(solution.value, Value::Win(1));
+        assert_eq!(solution.best_move, Some(Action::Drop(3)));
+    }
+
+    #[test]
+    fn sees_a_forced_loss() {
+        // Red threatens both ends of its pair, so yellow can only block one
+        let solution = solve_position("4x5x3 5/5/5/1rr1y y").unwrap();
+        assert_eq!(solution.value, Value::Loss(2));
+        assert!(solution.best_move.is_some());
+    }
+
+    #[test]
+    fn a_full_board_is_a_draw() {
+        let solution = solve_position("3x3x3 ryr/ryr/yry y").unwrap();
+        assert_eq!(solution.value, Value::Draw);
+        assert_eq!(solution.best_move, None);
+    }
+
+    #[test]
+    fn distances_count_down_along_the_best_line() {
+        // The first player wins 4x4x3 with their fifth piece
+        let (mut board, mut turn) = Board::from_notation("4x4x3 4/4/4/4 r").unwrap();
+        let mut table = TranspositionTable::new(1);
+
+        for left in (1..=9).rev() {
+            let solution = solve(&board, &turn, &mut table, frozen_clock, f64::INFINITY).unwrap();
+            let expected = if left % 2 == 1 {
+                Value::Win(left)
+            } else {
+                Value::Loss(left)
+            };
+            assert_eq!(solution.value, expected, "{}", board.to_notation(&turn));
+            board.play(solution.best_move.unwrap(), &turn);
+            turn = turn.next(2);
+        }
+
+        assert_eq!(board.game_state(), GameState::Win(Turn::Player1));
+    }
+
+    #[test]
+    fn large_boards_are_left_to_alpha_beta() {
+        let position = "8x9x4 9/9/9/9/9/9/9/9 r";
+        let (board, turn) = Board::from_notation(position).unwrap();
+        assert!(board.rows() * board.cols() > MAX_SOLVE_CELLS);
+        assert!(!can_solve(&board));
+        assert_eq!(solve_position(position), None);
+
+        let agent = crate::agent::Agent::Solver(SearchLimits {
+            think_time: 1.0,
+            max_depth: 2,
+        });
+        let mut search = TurnSearch::new(&agent, &board, &turn, frozen_clock);
+        let mut table = TranspositionTable::new(1);
+        let chosen = search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert!(matches!(chosen, Some(Some(Action::Drop(_)))));
+        assert_eq!(search.solution(), None);
+        assert_eq!(search.status(), None);
+    }
+
+    #[test]
+    fn the_solver_agent_reports_its_solution() {
+        let (board, turn) = Board::from_notation("6x7x4 7/7/7/7/yyy4/rrr4 r").unwrap();
+        let agent = crate::agent::Agent::Solver(SearchLimits::default());
+        let mut search = TurnSearch::new(&agent, &board, &turn, frozen_clock);
+        let mut table = TranspositionTable::new(1);
+        let chosen = search.step(&mut table, f64::INFINITY, &AtomicBool::new(false));
+        assert_eq!(chosen, Some(Some(Action::Drop(3))));
+        assert_eq!(search.solution().unwrap().value, Value::Win(1));
+        assert_eq!(search.status().as_deref(), Some("Solved: win in 1 move"));
+    }
+}
@@ -0,0 +1,29 @@
+//! Where saved games are kept: a file in the working directory natively, and
+//! the browser's local storage on the web, keyed by the same name.
+
+use std::io;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write(name: &str, contents: &str) -> io::Result<()> {
+    std::fs::write(name, contents)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read(name: &str) -> io::Result<String> {
+    std::fs::read_to_string(name)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write(name: &str, contents: &str) -> io::Result<()> {
+    quad_storage::STORAGE.lock().unwrap().set(name, contents);
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read(name: &str) -> io::Result<String> {
+    quad_storage::STORAGE
+        .lock()
+        .unwrap()
+        .get(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no save with that name"))
+}
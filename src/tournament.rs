@@ -0,0 +1,484 @@
+//! Matches between computer agents, for comparing them objectively.
+//!
+//! Every pairing plays the same number of games on each board, swapping who
+//! moves first after every game so neither agent gets the first move more
+//! often.
+
+use std::{str::FromStr, sync::atomic::AtomicBool};
+
+use crate::{
+    agent::{Agent, Clock, TurnSearch},
+    board::{Action, GameState},
+    game::Game,
+    notation::{parse_size, NotationError},
+    transposition::TranspositionTable,
+    Turn,
+};
+
+/// Which agents play each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Every agent plays every other agent
+    RoundRobin,
+    /// The first agent plays every other agent, who don't play each other
+    Gauntlet,
+}
+
+/// A board size and how many pieces in a row win on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardSize {
+    pub rows: usize,
+    pub cols: usize,
+    pub x_to_win: usize,
+}
+
+impl FromStr for BoardSize {
+    type Err = NotationError;
+
+    /// Reads a size written as in a position, such as `6x7x4`. Tournaments
+    /// are only between two players.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (rows, cols, x_to_win, players) = parse_size(text)?;
+        if players != 2 {
+            return Err(NotationError::InvalidSize(text.to_owned()));
+        }
+        Ok(BoardSize {
+            rows,
+            cols,
+            x_to_win,
+        })
+    }
+}
+
+/// The games one agent has played against another
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Record {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl Record {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Wins plus half the draws
+    pub fn score(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+
+    fn add(&mut self, other: &Record) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+}
+
+/// A rating on the Elo scale with the margin of its 95% confidence interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rating {
+    pub elo: f64,
+    pub margin: f64,
+}
+
+/// A finished game from the point of view of the agents in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameResult {
+    /// The indices of the agents moving first and second
+    pub players: [usize; 2],
+    pub size: BoardSize,
+    pub state: GameState,
+    pub moves: Vec<Action>,
+    /// Why an agent had to play a random move instead of its own, for each
+    /// time that happened
+    pub errors: Vec<String>,
+}
+
+/// Everything played so far in a tournament
+#[derive(Debug, Clone)]
+pub struct Standings {
+    /// `records[a][b]` is how agent `a` did against agent `b`
+    pub records: Vec<Vec<Record>>,
+    /// Seconds each agent has spent thinking, and over how many moves
+    pub think_time: Vec<(f64, usize)>,
+}
+
+impl Standings {
+    fn new(agents: usize) -> Self {
+        Standings {
+            records: vec![vec![Record::default(); agents]; agents],
+            think_time: vec![(0.0, 0); agents],
+        }
+    }
+
+    /// Every game an agent has played, against anyone
+    pub fn total(&self, agent: usize) -> Record {
+        let mut total = Record::default();
+        for record in &self.records[agent] {
+            total.add(record);
+        }
+        total
+    }
+
+    pub fn average_move_time(&self, agent: usize) -> f64 {
+        let (time, moves) = self.think_time[agent];
+        if moves == 0 {
+            0.0
+        } else {
+            time / moves as f64
+        }
+    }
+
+    /// Elo ratings relative to the field. The fitted strengths are scaled so
+    /// their geometric mean is 1, which puts the mean Elo at 0.
+    ///
+    /// The ratings are the Bradley-Terry maximum likelihood estimate, with a
+    /// draw counting as half a win. Every agent is also given one extra drawn
+    /// game against an average opponent, so a clean sweep still has a finite
+    /// rating. The margins come from how many games the rating rests on, so
+    /// they shrink as more games are played.
+    pub fn ratings(&self) -> Vec<Rating> {
+        let agents = self.records.len();
+        let mut strength = vec![1.0_f64; agents];
+
+        for _ in 0..1000 {
+            let mut next: Vec<f64> = (0..agents)
+                .map(|a| {
+                    let score = self.total(a).score() + 0.5;
+                    let expected: f64 = 1.0 / (strength[a] + 1.0)
+                        + self.records[a]
+                            .iter()
+                            .enumerate()
+                            .map(|(b, record)| record.games() as f64 / (strength[a] + strength[b]))
+                            .sum::<f64>();
+                    score / expected
+                })
+                .collect();
+
+            let mean = next.iter().map(|s| s.ln()).sum::<f64>() / agents as f64;
+            next.iter_mut().for_each(|s| *s /= mean.exp());
+            strength = next;
+        }
+
+        let scale = 400.0 / std::f64::consts::LN_10;
+        (0..agents)
+            .map(|a| {
+                let information = strength[a] / (strength[a] + 1.0).powi(2)
+                    + self.records[a]
+                        .iter()
+                        .enumerate()
+                        .map(|(b, record)| {
+                            record.games() as f64 * strength[a] * strength[b]
+                                / (strength[a] + strength[b]).powi(2)
+                        })
+                        .sum::<f64>();
+                Rating {
+                    elo: scale * strength[a].ln(),
+                    margin: 1.96 * scale / information.sqrt(),
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct Tournament {
+    agents: Vec<Agent>,
+    sizes: Vec<BoardSize>,
+    /// Games each pairing plays on each board
+    games: usize,
+    format: Format,
+    clock: Clock,
+    tables: Vec<TranspositionTable>,
+    standings: Standings,
+}
+
+impl Tournament {
+    /// Sets up a tournament where every agent gets its own transposition table
+    /// of `table_mb` megabytes. None of the agents can be [`Agent::Player`]
+    /// or [`Agent::Remote`].
+    pub fn new(
+        agents: Vec<Agent>,
+        sizes: Vec<BoardSize>,
+        games: usize,
+        format: Format,
+        table_mb: usize,
+        clock: Clock,
+    ) -> Self {
+        assert!(
+            !agents.contains(&Agent::Player) && !agents.contains(&Agent::Remote),
+            "a tournament is only for computer agents"
+        );
+
+        Tournament {
+            tables: (0..agents.len())
+                .map(|_| TranspositionTable::new(table_mb))
+                .collect(),
+            standings: Standings::new(agents.len()),
+            agents,
+            sizes,
+            games,
+            format,
+            clock,
+        }
+    }
+
+    /// The indices of the agents moving first and second in each game, with
+    /// the board they play on, in the order they will be played.
+    pub fn schedule(&self) -> Vec<([usize; 2], BoardSize)> {
+        let n = self.agents.len();
+        let pairings: Vec<(usize, usize)> = match self.format {
+            Format::RoundRobin => (0..n)
+                .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+                .collect(),
+            Format::Gauntlet => (1..n).map(|b| (0, b)).collect(),
+        };
+
+        let mut schedule = vec![];
+        for &size in &self.sizes {
+            for &(a, b) in &pairings {
+                for game in 0..self.games {
+                    let players = if game % 2 == 0 { [a, b] } else { [b, a] };
+                    schedule.push((players, size));
+                }
+            }
+        }
+        schedule
+    }
+
+    /// Plays one game and adds it to the standings.
+    pub fn play(&mut self, players: [usize; 2], size: BoardSize) -> GameResult {
+        let mut game = Game::new(size.rows, size.cols);
+        game.verify(size.rows, size.cols, size.x_to_win);
+        for &player in &players {
+            self.tables[player].clear();
+        }
+
+        let mut errors = vec![];
+        while game.board().game_state() == GameState::OnGoing {
+            let turn = game.turn();
+            let player = players[turn.index()];
+
+            let start = (self.clock)();
+            let mut search = TurnSearch::new(&self.agents[player], game.board(), &turn, self.clock);
+            let action = search
+                .step(
+                    &mut self.tables[player],
+                    f64::INFINITY,
+                    &AtomicBool::new(false),
+                )
+                .flatten()
+                .expect("an undecided game always has a move to make");
+            errors.extend(search.error().map(str::to_owned));
+            let think_time = &mut self.standings.think_time[player];
+            think_time.0 += (self.clock)() - start;
+            think_time.1 += 1;
+
+            game.play(action);
+        }
+
+        let state = game.board().game_state();
+        let win = Record {
+            wins: 1,
+            ..Record::default()
+        };
+        let loss = Record {
+            losses: 1,
+            ..Record::default()
+        };
+        let draw = Record {
+            draws: 1,
+            ..Record::default()
+        };
+        let (first_result, second_result) = match state {
+            GameState::Win(Turn::Player1) => (win, loss),
+            GameState::Win(_) => (loss, win),
+            GameState::Tie | GameState::OnGoing => (draw, draw),
+        };
+        let [first, second] = players;
+        self.standings.records[first][second].add(&first_result);
+        self.standings.records[second][first].add(&second_result);
+
+        GameResult {
+            players,
+            size,
+            state,
+            moves: game.history().iter().map(|m| m.action).collect(),
+            errors,
+        }
+    }
+
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    pub fn standings(&self) -> &Standings {
+        &self.standings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frozen_clock() -> f64 {
+        0.0
+    }
+
+    fn size(text: &str) -> BoardSize {
+        text.parse().unwrap()
+    }
+
+    fn tournament(agents: usize, sizes: &[&str], games: usize, format: Format) -> Tournament {
+        Tournament::new(
+            vec![Agent::Random; agents],
+            sizes.iter().map(|text| size(text)).collect(),
+            games,
+            format,
+            1,
+            frozen_clock,
+        )
+    }
+
+    /// How many games `a` moves first against `b`
+    fn firsts(schedule: &[([usize; 2], BoardSize)], a: usize, b: usize) -> usize {
+        schedule
+            .iter()
+            .filter(|(players, _)| *players == [a, b])
+            .count()
+    }
+
+    #[test]
+    fn round_robins_pair_everyone() {
+        let tournament = tournament(3, &["6x7x4", "5x5x4"], 4, Format::RoundRobin);
+        let schedule = tournament.schedule();
+        assert_eq!(schedule.len(), 2 * 3 * 4);
+        for (a, b) in [(0, 1), (0, 2), (1, 2)] {
+            assert_eq!(firsts(&schedule, a, b), 4);
+            assert_eq!(firsts(&schedule, b, a), 4);
+        }
+        assert!(schedule.iter().all(|([a, b], _)| a != b));
+        for text in ["6x7x4", "5x5x4"] {
+            let games = schedule.iter().filter(|(_, s)| *s == size(text)).count();
+            assert_eq!(games, 12);
+        }
+    }
+
+    #[test]
+    fn gauntlets_only_pair_the_first_agent() {
+        let tournament = tournament(4, &["6x7x4"], 2, Format::Gauntlet);
+        let schedule = tournament.schedule();
+        assert_eq!(schedule.len(), 3 * 2);
+        for b in 1..4 {
+            assert_eq!(firsts(&schedule, 0, b), 1);
+            assert_eq!(firsts(&schedule, b, 0), 1);
+        }
+        assert!(schedule.iter().all(|(players, _)| players.contains(&0)));
+    }
+
+    #[test]
+    fn results_are_credited_to_the_right_agent() {
+        // With one in a row to win the first move always wins
+        let mut tournament = tournament(2, &["2x2x1"], 2, Format::RoundRobin);
+        let result = tournament.play([0, 1], size("2x2x1"));
+        assert_eq!(result.state, GameState::Win(Turn::Player1));
+        assert_eq!(result.moves.len(), 1);
+        tournament.play([1, 0], size("2x2x1"));
+        tournament.play([1, 0], size("2x2x1"));
+
+        let records = &tournament.standings().records;
+        let one_win = |wins| Record {
+            wins,
+            draws: 0,
+            losses: 3 - wins,
+        };
+        assert_eq!(records[0][1], one_win(1));
+        assert_eq!(records[1][0], one_win(2));
+        assert_eq!(tournament.standings().think_time[1].1, 2);
+    }
+
+    #[test]
+    fn draws_count_for_both_agents() {
+        // Neither player can get two in a row across a single row of two,
+        // which is too narrow to be written as a size
+        let mut tournament = tournament(2, &[], 1, Format::RoundRobin);
+        let size = BoardSize {
+            rows: 1,
+            cols: 2,
+            x_to_win: 2,
+        };
+        let result = tournament.play([1, 0], size);
+        assert_eq!(result.state, GameState::Tie);
+        let draw = Record {
+            draws: 1,
+            ..Record::default()
+        };
+        assert_eq!(tournament.standings().records[0][1], draw);
+        assert_eq!(tournament.standings().records[1][0], draw);
+    }
+
+    /// Standings where agent 0 scored `wins` and `draws` against agent 1
+    fn standings(wins: usize, draws: usize, losses: usize) -> Standings {
+        let mut standings = Standings::new(2);
+        standings.records[0][1] = Record {
+            wins,
+            draws,
+            losses,
+        };
+        standings.records[1][0] = Record {
+            wins: losses,
+            draws,
+            losses: wins,
+        };
+        standings
+    }
+
+    #[test]
+    fn even_records_rate_the_same() {
+        for ratings in [standings(5, 0, 5).ratings(), standings(0, 10, 0).ratings()] {
+            assert!(ratings[0].elo.abs() < 1e-6, "{ratings:?}");
+            assert!(ratings[1].elo.abs() < 1e-6, "{ratings:?}");
+        }
+    }
+
+    #[test]
+    fn a_clean_sweep_has_a_finite_rating() {
+        let ratings = standings(20, 0, 0).ratings();
+        assert!(ratings[0].elo.is_finite() && ratings[0].elo > 0.0);
+        assert!((ratings[0].elo + ratings[1].elo).abs() < 1e-6);
+        assert!(ratings[0].margin.is_finite());
+    }
+
+    #[test]
+    fn margins_shrink_with_more_games() {
+        let few = standings(6, 2, 2).ratings();
+        let many = standings(60, 20, 20).ratings();
+        assert!(many[0].margin < few[0].margin);
+        // The extra drawn game pulls a small sample towards the middle more
+        assert!(few[0].elo < many[0].elo);
+        assert!(many[0].elo > 0.0);
+    }
+
+    #[test]
+    fn engine_failures_are_kept_with_the_game() {
+        let engine = Agent::External(crate::external::ExternalEngine {
+            command: "./no-such-engine".to_owned(),
+            ..Default::default()
+        });
+        let mut tournament = Tournament::new(
+            vec![engine, Agent::Random],
+            vec![size("2x2x1")],
+            1,
+            Format::RoundRobin,
+            1,
+            frozen_clock,
+        );
+        let result = tournament.play([0, 1], size("2x2x1"));
+        assert_eq!(result.state, GameState::Win(Turn::Player1));
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].contains("./no-such-engine"));
+
+        let result = tournament.play([1, 0], size("2x2x1"));
+        assert!(result.errors.is_empty());
+    }
+}
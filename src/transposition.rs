@@ -0,0 +1,147 @@
+use std::mem::size_of;
+
+use crate::board::Action;
+
+/// How a stored value relates to the true value of the position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The search finished inside its window, so the value is exact
+    Exact,
+    /// The search failed high, so the true value is at least this
+    Lower,
+    /// The search failed low, so the true value is at most this
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: u32,
+    pub value: i32,
+    pub bound: Bound,
+    pub best_move: Option<Action>,
+}
+
+/// A fixed size hash table of previously searched positions.
+///
+/// Entries are indexed by their Zobrist key modulo the number of slots. When
+/// two positions land in the same slot the one searched to the greater depth
+/// is kept.
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    megabytes: usize,
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let count = (megabytes * 1024 * 1024 / size_of::<Option<Entry>>()).max(1);
+        TranspositionTable {
+            entries: vec![None; count],
+            megabytes,
+        }
+    }
+
+    /// Resizes the table if the memory budget has changed, discarding its contents.
+    pub fn verify(&mut self, megabytes: usize) {
+        if self.megabytes != megabytes {
+            *self = TranspositionTable::new(megabytes);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    fn slot(&self, key: u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn get(&self, key: u64) -> Option<&Entry> {
+        self.entries[self.slot(key)]
+            .as_ref()
+            .filter(|entry| entry.key == key)
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let slot = self.slot(entry.key);
+        match &self.entries[slot] {
+            Some(old) if old.key != entry.key && old.depth > entry.depth => {}
+            _ => self.entries[slot] = Some(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: u64, depth: u32, value: i32) -> Entry {
+        Entry {
+            key,
+            depth,
+            value,
+            bound: Bound::Exact,
+            best_move: Some(Action::Drop(depth as usize)),
+        }
+    }
+
+    #[test]
+    fn stored_entries_are_found() {
+        let mut table = TranspositionTable::new(1);
+        assert!(table.get(42).is_none());
+
+        table.store(entry(42, 3, 7));
+        let found = table.get(42).unwrap();
+        assert_eq!((found.depth, found.value), (3, 7));
+        assert_eq!(found.best_move, Some(Action::Drop(3)));
+
+        table.clear();
+        assert!(table.get(42).is_none());
+    }
+
+    #[test]
+    fn the_same_position_is_always_replaced() {
+        let mut table = TranspositionTable::new(1);
+        table.store(entry(42, 5, 1));
+        table.store(entry(42, 2, -1));
+        assert_eq!(table.get(42).unwrap().value, -1);
+    }
+
+    #[test]
+    fn deeper_searches_keep_their_slot() {
+        let mut table = TranspositionTable::new(1);
+        let other = 42 + table.entries.len() as u64;
+
+        table.store(entry(42, 5, 1));
+        table.store(entry(other, 4, 2));
+        assert_eq!(table.get(42).unwrap().value, 1);
+        assert!(table.get(other).is_none());
+
+        // As deep or deeper takes the slot over
+        table.store(entry(other, 5, 2));
+        assert_eq!(table.get(other).unwrap().value, 2);
+        assert!(table.get(42).is_none());
+    }
+
+    #[test]
+    fn positions_sharing_a_slot_are_told_apart() {
+        let mut table = TranspositionTable::new(1);
+        let other = 42 + table.entries.len() as u64;
+        assert_eq!(table.slot(42), table.slot(other));
+
+        table.store(entry(42, 3, 7));
+        assert!(table.get(other).is_none());
+    }
+
+    #[test]
+    fn resizing_empties_the_table() {
+        let mut table = TranspositionTable::new(1);
+        table.store(entry(42, 3, 7));
+
+        table.verify(1);
+        assert!(table.get(42).is_some());
+        table.verify(2);
+        assert!(table.get(42).is_none());
+        assert!(table.entries.len() > TranspositionTable::new(1).entries.len());
+    }
+}
@@ -0,0 +1,57 @@
+//! The `engine` command, which plays as one of the built-in agents over the
+//! text protocol on standard input and output.
+
+use std::{io, process};
+
+use connect_x::{agent::Agent, protocol};
+
+const USAGE: &str = "\
+Usage: connect-x-tui engine [OPTIONS]
+
+Options:
+  --agent <AGENT>   Agent to play as, written as for --p1 [default: alphabeta]
+  --table-mb <N>    Transposition table size [default: 16]
+  --help            Print this message
+
+The think time of the agent is replaced by the movetime of each go command.";
+
+pub fn run(args: impl IntoIterator<Item = String>) {
+    let mut agent = Agent::AlphaBeta(Default::default());
+    let mut table_mb = 16;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+            None => (arg.clone(), None),
+        };
+        let value = if name == "--help" {
+            None
+        } else {
+            value.or_else(|| args.next())
+        };
+
+        let parsed = match (name.as_str(), value) {
+            ("--help", _) => {
+                println!("{USAGE}");
+                return;
+            }
+            ("--agent", Some(value)) => value.parse().map(|a| agent = a).ok(),
+            ("--table-mb", Some(value)) => value
+                .parse()
+                .ok()
+                .filter(|&mb| mb > 0)
+                .map(|mb| table_mb = mb),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("error: '{arg}' is not a valid option\n\n{USAGE}");
+            process::exit(2);
+        }
+    }
+
+    if let Err(err) = protocol::serve(&agent, table_mb, io::stdin().lock(), io::stdout()) {
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,343 @@
+//! Connect X in the terminal, for machines where a window can't be opened.
+//!
+//! `connect-x-tui tournament` plays computer agents against each other instead
+//! of starting a game, and `connect-x-tui engine` plays as an agent over the
+//! text protocol for other programs to use.
+
+use std::{
+    io::{self, BufRead, Write},
+    process,
+    sync::atomic::AtomicBool,
+    thread,
+    time::Duration,
+};
+
+use connect_x::{
+    agent::{system_clock, Agent, TurnSearch},
+    board::{Action, Board, GameState, Pieces},
+    cli::{CliError, Settings, USAGE},
+    game::Game,
+    notation::parse_action,
+    transposition::TranspositionTable,
+    Turn, MAX_PLAYERS,
+};
+
+mod engine;
+mod tournament;
+
+const TABLE_MB: usize = 64;
+
+const COMMANDS: &str = "\
+Usage: connect-x-tui [OPTIONS]
+       connect-x-tui tournament --help
+       connect-x-tui engine --help";
+
+// ANSI escape codes
+const RESET: &str = "\x1b[0m";
+const CLEAR: &str = "\x1b[2J\x1b[H";
+/// The escape code and name of the colour of each player's pieces
+const PLAYER_COLORS: [(&str, &str); MAX_PLAYERS] = [
+    ("\x1b[31m", "red"),
+    ("\x1b[33m", "yellow"),
+    ("\x1b[32m", "green"),
+    ("\x1b[34m", "blue"),
+    ("\x1b[35m", "magenta"),
+    ("\x1b[38;5;208m", "orange"),
+    ("\x1b[36m", "cyan"),
+    ("\x1b[37m", "white"),
+];
+const EMPTY_COLOR: &str = "\x1b[2m";
+const BLOCKED_COLOR: &str = "\x1b[90m";
+const HIGHLIGHT: &str = "\x1b[1;7m";
+
+fn player_name(turn: Turn) -> String {
+    format!("{turn} ({})", PLAYER_COLORS[turn.index()].1)
+}
+
+/// Draws the board with column numbers along the top, and row numbers down
+/// the side when there is no gravity, highlighting the cells of any winning
+/// line.
+fn render(board: &Board) -> String {
+    let winning: Vec<(usize, usize)> = board
+        .winning_lines()
+        .iter()
+        .flat_map(|line| line.cells().collect::<Vec<_>>())
+        .collect();
+    let width = board.cols().to_string().len() + 1;
+    let row_width = if board.rules().gravity {
+        0
+    } else {
+        board.rows().to_string().len()
+    };
+
+    let mut out = " ".repeat(row_width);
+    for col in 0..board.cols() {
+        out.push_str(&format!("{:>width$}", col + 1));
+    }
+    out.push('\n');
+
+    for row in 0..board.rows() {
+        if row_width > 0 {
+            out.push_str(&format!("{:>row_width$}", row + 1));
+        }
+        for col in 0..board.cols() {
+            let (color, symbol) = match board.piece_at(row, col) {
+                Pieces::Player(turn) => (PLAYER_COLORS[turn.index()].0, '●'),
+                Pieces::Empty => (EMPTY_COLOR, '·'),
+                Pieces::Blocked => (BLOCKED_COLOR, '■'),
+            };
+            let highlight = if winning.contains(&(row, col)) {
+                HIGHLIGHT
+            } else {
+                ""
+            };
+            out.push_str(&format!(
+                "{}{color}{highlight}{symbol}{RESET}",
+                " ".repeat(width - 1)
+            ));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Prints `prompt` and reads a line, exiting if input has run out.
+fn read_line(stdin: &mut impl BufRead, prompt: &str) -> String {
+    print!("{prompt}");
+    io::stdout().flush().unwrap();
+
+    let mut line = String::new();
+    if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+        println!();
+        process::exit(0);
+    }
+    line.trim().to_owned()
+}
+
+/// Asks the player for a move until they give one that can be played.
+fn player_turn(stdin: &mut impl BufRead, game: &Game) -> Action {
+    let (rows, cols) = (game.board().rows(), game.board().cols());
+    let rules = game.board().rules();
+    let prompt = if !rules.gravity {
+        format!(
+            "{}, pick a cell such as r{rows}c1 or q to quit: ",
+            player_name(game.turn())
+        )
+    } else {
+        let pop = if rules.popout {
+            format!(", p1-p{cols} to pop")
+        } else {
+            String::new()
+        };
+        format!(
+            "{}, pick a column 1-{cols}{pop} or q to quit: ",
+            player_name(game.turn())
+        )
+    };
+    loop {
+        let line = read_line(stdin, &prompt);
+        if line.eq_ignore_ascii_case("q") {
+            process::exit(0);
+        }
+        match parse_action(&line) {
+            Some(action) if game.board().moves(&game.turn()).contains(&action) => return action,
+            Some(Action::Drop(col)) if col < cols => println!("Column {} is full", col + 1),
+            Some(Action::Pop(col)) if col < cols && rules.popout && rules.gravity => {
+                println!("You can't pop from column {}", col + 1)
+            }
+            Some(Action::Put(row, col)) if row < rows && col < cols && !rules.gravity => {
+                println!("Row {}, column {} is taken", row + 1, col + 1)
+            }
+            _ if !rules.gravity => println!("'{line}' is not a cell"),
+            _ => println!("'{line}' is not a column"),
+        }
+    }
+}
+
+fn play(settings: &Settings, table: &mut TranspositionTable, stdin: &mut impl BufRead) {
+    let mut game = Game::new(settings.rows, settings.cols);
+    game.verify(settings.rows, settings.cols, settings.x_val);
+    game.set_players(settings.players);
+    game.set_rules(settings.rules);
+    game.set_shape(&settings.shape.blocked(settings.rows, settings.cols));
+    let mut last_move = String::new();
+
+    while game.board().game_state() == GameState::OnGoing {
+        print!("{CLEAR}{}{last_move}", render(game.board()));
+
+        let turn = game.turn();
+        let agent = &settings.agents[turn.index()];
+        let mut status = None;
+        let action = match agent {
+            Agent::Player => player_turn(stdin, &game),
+            _ => {
+                println!("{} ({}) is thinking…", player_name(turn), agent.name());
+                let mut search = TurnSearch::new(agent, game.board(), &turn, system_clock);
+                let action = search
+                    .step(table, f64::INFINITY, &AtomicBool::new(false))
+                    .flatten()
+                    .expect("the game is not over, so there is a move to make");
+                status = search.status();
+                thread::sleep(Duration::from_secs_f64(settings.sleep_time));
+                action
+            }
+        };
+
+        game.play(action);
+        last_move = match action {
+            Action::Drop(col) => format!("{} played column {}\n", player_name(turn), col + 1),
+            Action::Pop(col) => format!("{} popped column {}\n", player_name(turn), col + 1),
+            Action::Put(row, col) => format!(
+                "{} played row {}, column {}\n",
+                player_name(turn),
+                row + 1,
+                col + 1
+            ),
+        };
+        if let Some(status) = status {
+            last_move += &format!("{} ({}): {status}\n", player_name(turn), agent.name());
+        }
+    }
+
+    print!("{CLEAR}{}{last_move}", render(game.board()));
+    match game.board().game_state() {
+        GameState::Win(turn) => println!("{} wins!", player_name(turn)),
+        GameState::Tie | GameState::OnGoing => println!("It's a tie"),
+    }
+    println!("Moves: {}", game.to_move_notation());
+}
+
+/// A seed for the random agents that differs from run to run
+fn clock_seed() -> u64 {
+    (system_clock() * 1e6) as u64
+}
+
+fn main() {
+    quad_rand::srand(clock_seed());
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("tournament") => {
+            args.next();
+            tournament::run(args);
+            return;
+        }
+        Some("engine") => {
+            args.next();
+            engine::run(args);
+            return;
+        }
+        _ => {}
+    }
+
+    let settings = match Settings::from_args(args) {
+        Ok(settings) => settings,
+        Err(CliError::Help) => {
+            println!("{COMMANDS}\n\n{USAGE}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err}\n\nRun with --help to see the options");
+            process::exit(2);
+        }
+    };
+
+    let mut table = TranspositionTable::new(TABLE_MB);
+    let mut stdin = io::stdin().lock();
+    loop {
+        play(&settings, &mut table, &mut stdin);
+        let again = read_line(&mut stdin, "Play again? [y/N] ");
+        if !again.eq_ignore_ascii_case("y") {
+            break;
+        }
+        table.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use connect_x::board::Rules;
+
+    use super::*;
+
+    /// `text` with the colour escape codes taken out
+    fn plain(text: &str) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    fn position(text: &str, rules: Rules) -> (Board, Turn) {
+        Board::from_notation_with(text, rules).unwrap()
+    }
+
+    #[test]
+    fn boards_are_drawn_with_their_column_numbers() {
+        let (board, _) = position("4x4x3 4/4/2#1/ry2 r", Rules::default());
+        assert_eq!(
+            plain(&render(&board)),
+            " 1 2 3 4\n · · · ·\n · · · ·\n · · ■ ·\n ● ● · ·\n"
+        );
+    }
+
+    #[test]
+    fn rows_are_numbered_without_gravity() {
+        let rules = Rules {
+            gravity: false,
+            ..Rules::default()
+        };
+        let (board, _) = position("3x3x3 r2/3/2y y", rules);
+        assert_eq!(
+            plain(&render(&board)),
+            "  1 2 3\n1 ● · ·\n2 · · ·\n3 · · ●\n"
+        );
+    }
+
+    #[test]
+    fn winning_cells_are_highlighted() {
+        let (board, _) = position("4x4x3 4/4/yy2/rrr1 y", Rules::default());
+        let out = render(&board);
+        let red = PLAYER_COLORS[0].0;
+        assert_eq!(out.matches(&format!("{red}{HIGHLIGHT}●")).count(), 3);
+        assert_eq!(out.matches(HIGHLIGHT).count(), 3);
+    }
+
+    #[test]
+    fn players_are_asked_again_until_their_move_can_be_played() {
+        let (board, turn) = position("2x3x2 r2/y2 y", Rules::default());
+        let mut game = Game::new(2, 3);
+        game.set_position(board, turn);
+
+        let mut input = io::Cursor::new("1\n9\nfoo\n2\n");
+        assert_eq!(player_turn(&mut input, &game), Action::Drop(1));
+
+        let rules = Rules {
+            gravity: false,
+            ..Rules::default()
+        };
+        let (board, turn) = position("3x3x3 r2/3/3 y", rules);
+        game.set_position(board, turn);
+        let mut input = io::Cursor::new("r1c1\n2\nr2c3\n");
+        assert_eq!(player_turn(&mut input, &game), Action::Put(1, 2));
+    }
+
+    #[test]
+    fn games_between_computers_play_to_the_end() {
+        let settings = Settings {
+            rows: 4,
+            cols: 4,
+            x_val: 3,
+            agents: vec![Agent::Random; MAX_PLAYERS],
+            ..Settings::default()
+        };
+        let mut table = TranspositionTable::new(1);
+        // Nobody reads from the input
+        play(&settings, &mut table, &mut io::Cursor::new(""));
+    }
+}
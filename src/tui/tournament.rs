@@ -0,0 +1,223 @@
+//! The `tournament` command, which plays computer agents against each other
+//! without any input and prints how they did.
+
+use std::process;
+
+use connect_x::{
+    agent::{system_clock, Agent},
+    board::GameState,
+    notation::format_moves,
+    tournament::{BoardSize, Format, Tournament},
+    Turn,
+};
+
+use crate::clock_seed;
+
+const USAGE: &str = "\
+Usage: connect-x-tui tournament --agents <LIST> [OPTIONS]
+
+Options:
+  --agents <LIST>   Comma separated agents to play, at least two
+  --sizes <LIST>    Comma separated boards as <rows>x<cols>x<x> [default: 6x7x4]
+  --games <N>       Games each pairing plays on each board [default: 2]
+  --gauntlet        Only play the first agent against each of the others
+  --table-mb <N>    Transposition table size for each agent [default: 16]
+  --seed <N>        Seed for the agents' random choices, which repeats the
+                    games of agents that aren't limited by time
+                    [default: taken from the clock]
+  --help            Print this message
+
+Agents are written as for --p1 and --p2, such as random, alphabeta:0.5:4 or
+mcts:0.5. Player is not allowed.";
+
+struct Options {
+    labels: Vec<String>,
+    agents: Vec<Agent>,
+    sizes: Vec<BoardSize>,
+    games: usize,
+    format: Format,
+    table_mb: usize,
+    seed: Option<u64>,
+}
+
+fn parse_options(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        labels: vec![],
+        agents: vec![],
+        sizes: vec![BoardSize {
+            rows: 6,
+            cols: 7,
+            x_to_win: 4,
+        }],
+        games: 2,
+        format: Format::RoundRobin,
+        table_mb: 16,
+        seed: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || {
+            value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("'{name}' needs a value"))
+        };
+
+        match name.as_str() {
+            "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            "--gauntlet" => options.format = Format::Gauntlet,
+            "--agents" => {
+                options.labels = value()?.split(',').map(str::to_owned).collect();
+                options.agents = options
+                    .labels
+                    .iter()
+                    .map(|label| match label.parse() {
+                        Ok(Agent::Player) | Err(()) => {
+                            Err(format!("'{label}' is not a computer agent"))
+                        }
+                        Ok(agent) => Ok(agent),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--sizes" => {
+                options.sizes = value()?
+                    .split(',')
+                    .map(|size| size.parse().map_err(|err| format!("{err}")))
+                    .collect::<Result<_, _>>()?;
+            }
+            "--games" => {
+                let games = value()?;
+                options.games = games
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("'{games}' is not a number of games"))?;
+            }
+            "--table-mb" => {
+                let mb = value()?;
+                options.table_mb = mb
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("'{mb}' is not a table size"))?;
+            }
+            "--seed" => {
+                let seed = value()?;
+                options.seed = Some(
+                    seed.parse()
+                        .map_err(|_| format!("'{seed}' is not a seed"))?,
+                );
+            }
+            _ => return Err(format!("unknown option '{arg}'")),
+        }
+    }
+
+    if options.agents.len() < 2 {
+        return Err("--agents needs at least two agents".to_owned());
+    }
+    Ok(options)
+}
+
+fn describe(state: GameState) -> &'static str {
+    match state {
+        GameState::Win(Turn::Player1) => "1-0",
+        GameState::Win(_) => "0-1",
+        GameState::Tie | GameState::OnGoing => "½-½",
+    }
+}
+
+fn print_results(tournament: &Tournament, labels: &[String]) {
+    let standings = tournament.standings();
+    let ratings = standings.ratings();
+    let width = labels.iter().map(|l| l.len()).max().unwrap_or(0).max(5);
+
+    // Strongest agents first
+    let mut order: Vec<usize> = (0..labels.len()).collect();
+    order.sort_by(|&a, &b| ratings[b].elo.total_cmp(&ratings[a].elo));
+
+    println!("\nCrosstable (wins-draws-losses of the row against the column)\n");
+    print!("{:>3}  {:<width$}", "", "Agent");
+    for rank in 1..=order.len() {
+        print!(" {rank:>9}");
+    }
+    println!(
+        " {:>7} {:>6} {:>14} {:>10}",
+        "Score", "Games", "Elo", "Avg move"
+    );
+
+    for (rank, &a) in order.iter().enumerate() {
+        print!("{:>3}  {:<width$}", rank + 1, labels[a]);
+        for &b in &order {
+            let record = standings.records[a][b];
+            if a == b || record.games() == 0 {
+                print!(" {:>9}", "-");
+            } else {
+                let cell = format!("{}-{}-{}", record.wins, record.draws, record.losses);
+                print!(" {cell:>9}");
+            }
+        }
+        let total = standings.total(a);
+        let rating = ratings[a];
+        println!(
+            " {:>7.1} {:>6} {:>14} {:>9.3}s",
+            total.score(),
+            total.games(),
+            format!("{:+.0} ± {:.0}", rating.elo, rating.margin),
+            standings.average_move_time(a)
+        );
+    }
+}
+
+pub fn run(args: impl IntoIterator<Item = String>) {
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    // Printed so that a tournament worth a second look can be played again
+    let seed = options.seed.unwrap_or_else(clock_seed);
+    quad_rand::srand(seed);
+    println!("Seed: {seed}");
+
+    let mut tournament = Tournament::new(
+        options.agents,
+        options.sizes,
+        options.games,
+        options.format,
+        options.table_mb,
+        system_clock,
+    );
+
+    let schedule = tournament.schedule();
+    for (i, &(players, size)) in schedule.iter().enumerate() {
+        let result = tournament.play(players, size);
+        println!(
+            "Game {}/{}: {} vs {} on {}x{}x{}: {} {}",
+            i + 1,
+            schedule.len(),
+            options.labels[players[0]],
+            options.labels[players[1]],
+            size.rows,
+            size.cols,
+            size.x_to_win,
+            describe(result.state),
+            format_moves(size.cols, result.moves)
+        );
+        for error in result.errors {
+            println!("  {error}");
+        }
+    }
+
+    print_results(&tournament, &options.labels);
+}
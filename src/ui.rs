@@ -1,6 +1,12 @@
 use std::ops::RangeInclusive;
 
-use crate::agent::Agent;
+use connect_x::{
+    agent::{Agent, SearchLimits},
+    board::Action,
+    external::ExternalEngine,
+    game::Game,
+    mcts::MctsLimit,
+};
 
 pub fn number_drag(ui: &mut egui::Ui, val: &mut usize, text: &str, range: RangeInclusive<usize>) {
     ui.columns(2, |columns| {
@@ -9,16 +15,161 @@ pub fn number_drag(ui: &mut egui::Ui, val: &mut usize, text: &str, range: RangeI
     });
 }
 
-pub fn agent_selector(ui: &mut egui::Ui, text: &str, agent: &mut Agent) {
+/// A labelled block of text with a button to copy it to the clipboard
+pub fn copyable_text(ui: &mut egui::Ui, text: &str, value: String) {
+    ui.horizontal(|ui| {
+        ui.label(text);
+        if ui.small_button("Copy").clicked() {
+            ui.output().copied_text = value.clone();
+        }
+    });
+    ui.label(egui::RichText::new(value).monospace());
+}
+
+/// Every move played so far, newest at the bottom, so someone watching can
+/// follow along.
+pub fn move_list(ui: &mut egui::Ui, game: &Game) {
+    egui::ScrollArea::vertical()
+        .max_height(150.0)
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            for (ply, m) in game.history().iter().enumerate() {
+                let action = match m.action {
+                    Action::Drop(col) => format!("column {}", col + 1),
+                    Action::Pop(col) => format!("popped column {}", col + 1),
+                    Action::Put(row, col) => format!("row {}, column {}", row + 1, col + 1),
+                };
+                ui.label(format!("{}. {}: {action}", ply + 1, m.turn));
+            }
+        });
+}
+
+/// Buttons and a slider to move through a finished game. Returns true if the
+/// position shown changed.
+pub fn replay_controls(ui: &mut egui::Ui, game: &mut Game, autoplay: &mut bool) -> bool {
+    let start = game.ply();
+    let mut ply = start;
+
+    ui.horizontal(|ui| {
+        if ui.button("|<").clicked() {
+            ply = 0;
+        }
+        if ui.button("<").clicked() {
+            ply = ply.saturating_sub(1);
+        }
+        let label = if *autoplay { "Pause" } else { "Play" };
+        if ui.button(label).clicked() {
+            *autoplay = !*autoplay;
+            // Playing from the last move starts again from the beginning
+            if *autoplay && ply == game.plies() {
+                ply = 0;
+            }
+        }
+        if ui.button(">").clicked() {
+            ply += 1;
+        }
+        if ui.button(">|").clicked() {
+            ply = game.plies();
+        }
+    });
+    ui.add(egui::Slider::new(&mut ply, 0..=game.plies()).text("Ply"));
+
+    game.seek(ply);
+    game.ply() != start
+}
+
+/// Chooses the agent for a player, along with the colour of their pieces.
+pub fn agent_selector(ui: &mut egui::Ui, text: &str, agent: &mut Agent, color: &mut [f32; 3]) {
     ui.columns(2, |columns| {
-        columns[0].label(text);
+        columns[0].horizontal(|ui| {
+            ui.color_edit_button_rgb(color);
+            ui.label(text);
+        });
 
         egui::ComboBox::from_id_source(text)
-            .selected_text(format!("{:?}", agent))
+            .selected_text(agent.name())
             .show_ui(&mut columns[1], |ui| {
                 ui.selectable_value(agent, Agent::Player, "Player");
                 ui.selectable_value(agent, Agent::Random, "Random");
-                ui.selectable_value(agent, Agent::AlphaBeta, "AlphaBeta");
+                if ui
+                    .selectable_label(matches!(agent, Agent::AlphaBeta(_)), "AlphaBeta")
+                    .clicked()
+                    && !matches!(agent, Agent::AlphaBeta(_))
+                {
+                    *agent = Agent::AlphaBeta(SearchLimits::default());
+                }
+                if ui
+                    .selectable_label(matches!(agent, Agent::Mcts(_)), "MCTS")
+                    .clicked()
+                    && !matches!(agent, Agent::Mcts(_))
+                {
+                    *agent = Agent::Mcts(MctsLimit::default());
+                }
+                if ui
+                    .selectable_label(matches!(agent, Agent::Solver(_)), "Solver")
+                    .clicked()
+                    && !matches!(agent, Agent::Solver(_))
+                {
+                    *agent = Agent::Solver(SearchLimits::default());
+                }
+                // Programs can't be started from the web
+                if cfg!(not(target_arch = "wasm32"))
+                    && ui
+                        .selectable_label(matches!(agent, Agent::External(_)), "External")
+                        .clicked()
+                    && !matches!(agent, Agent::External(_))
+                {
+                    *agent = Agent::External(ExternalEngine::default());
+                }
+                // Nor can connections be made
+                if cfg!(not(target_arch = "wasm32")) {
+                    ui.selectable_value(agent, Agent::Remote, "Remote");
+                }
             });
     });
+
+    match agent {
+        Agent::AlphaBeta(limits) | Agent::Solver(limits) => limits_editor(ui, limits),
+        Agent::Mcts(limit) => mcts_limit_editor(ui, limit),
+        Agent::External(engine) => external_editor(ui, engine),
+        _ => {}
+    }
+}
+
+fn limits_editor(ui: &mut egui::Ui, limits: &mut SearchLimits) {
+    ui.add(egui::Slider::new(&mut limits.think_time, 0.1..=10.0).text("Think Time"));
+    ui.add(egui::Slider::new(&mut limits.max_depth, 1..=64).text("Max Depth"));
+}
+
+fn external_editor(ui: &mut egui::Ui, engine: &mut ExternalEngine) {
+    ui.horizontal(|ui| {
+        ui.label("Command:");
+        ui.text_edit_singleline(&mut engine.command);
+    });
+    ui.add(egui::Slider::new(&mut engine.think_time, 0.1..=10.0).text("Think Time"));
+}
+
+fn mcts_limit_editor(ui: &mut egui::Ui, limit: &mut MctsLimit) {
+    ui.horizontal(|ui| {
+        let by_time = matches!(limit, MctsLimit::Time(_));
+        if ui.radio(by_time, "Time").clicked() && !by_time {
+            *limit = MctsLimit::Time(1.0);
+        }
+        if ui.radio(!by_time, "Iterations").clicked() && by_time {
+            *limit = MctsLimit::Iterations(10_000);
+        }
+    });
+
+    match limit {
+        MctsLimit::Time(think_time) => {
+            ui.add(egui::Slider::new(think_time, 0.1..=10.0).text("Think Time"));
+        }
+        MctsLimit::Iterations(iterations) => {
+            ui.add(
+                egui::Slider::new(iterations, 100..=1_000_000)
+                    .logarithmic(true)
+                    .text("Iterations"),
+            );
+        }
+    }
 }
@@ -0,0 +1,183 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use connect_x::{
+    agent::{Agent, TurnSearch},
+    board::{Action, Board},
+    transposition::TranspositionTable,
+    Turn,
+};
+
+/// A computer agent thinking about its move away from the render loop.
+///
+/// Natively the search runs on its own thread. Threads are not available on
+/// the web, so there the search is advanced for a short slice every frame
+/// instead. Dropping the worker cancels the search.
+pub struct Worker {
+    cancel: Arc<AtomicBool>,
+    #[cfg(not(target_arch = "wasm32"))]
+    result: std::sync::mpsc::Receiver<(Option<Action>, Option<String>)>,
+    /// The search's status, once it has finished
+    #[cfg(not(target_arch = "wasm32"))]
+    status: Option<String>,
+    #[cfg(target_arch = "wasm32")]
+    search: TurnSearch,
+    #[cfg(target_arch = "wasm32")]
+    table: Arc<Mutex<TranspositionTable>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Worker {
+    pub fn spawn(
+        agent: &Agent,
+        board: &Board,
+        turn: &Turn,
+        table: Arc<Mutex<TranspositionTable>>,
+    ) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, result) = std::sync::mpsc::channel();
+
+        // macroquad's clock belongs to the main thread
+        let mut search = TurnSearch::new(agent, board, turn, connect_x::agent::system_clock);
+        let thread_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            let mut table = table.lock().unwrap();
+            let chosen = search.step(&mut table, f64::INFINITY, &thread_cancel);
+            // The receiver is gone if the worker was dropped, which is fine
+            let _ = sender.send((chosen.flatten(), search.status()));
+        });
+
+        Worker {
+            cancel,
+            result,
+            status: None,
+        }
+    }
+
+    /// Returns the chosen move once the agent has finished thinking.
+    pub fn poll(&mut self) -> Option<Option<Action>> {
+        let (chosen, status) = self.result.try_recv().ok()?;
+        self.status = status;
+        Some(chosen)
+    }
+
+    /// What the agent had to say about its move, see [`TurnSearch::status`].
+    pub fn status(&self) -> Option<String> {
+        self.status.clone()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Worker {
+    /// Time given to the search each frame, in seconds
+    const SLICE: f64 = 0.010;
+
+    pub fn spawn(
+        agent: &Agent,
+        board: &Board,
+        turn: &Turn,
+        table: Arc<Mutex<TranspositionTable>>,
+    ) -> Self {
+        Worker {
+            cancel: Arc::new(AtomicBool::new(false)),
+            search: TurnSearch::new(agent, board, turn, macroquad::time::get_time),
+            table,
+        }
+    }
+
+    /// Returns the chosen move once the agent has finished thinking.
+    pub fn poll(&mut self) -> Option<Option<Action>> {
+        let mut table = self.table.lock().unwrap();
+        let until = macroquad::time::get_time() + Self::SLICE;
+        self.search.step(&mut table, until, &self.cancel)
+    }
+
+    /// What the agent had to say about its move, see [`TurnSearch::status`].
+    pub fn status(&self) -> Option<String> {
+        self.search.status()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use connect_x::agent::SearchLimits;
+
+    use super::*;
+
+    /// Polls the worker until it has decided, failing after `timeout`
+    fn wait(worker: &mut Worker, timeout: Duration) -> Option<Action> {
+        let start = Instant::now();
+        loop {
+            if let Some(chosen) = worker.poll() {
+                return chosen;
+            }
+            assert!(start.elapsed() < timeout, "the worker never finished");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn board() -> Board {
+        Board::from_notation("6x7x4 7/7/7/7/7/7 r").unwrap().0
+    }
+
+    #[test]
+    fn the_chosen_move_comes_back_from_the_thread() {
+        let table = Arc::new(Mutex::new(TranspositionTable::new(1)));
+        let mut worker = Worker::spawn(&Agent::Random, &board(), &Turn::Player1, table);
+        let chosen = wait(&mut worker, Duration::from_secs(10));
+        assert!(matches!(chosen, Some(Action::Drop(_))));
+    }
+
+    #[test]
+    fn polling_does_not_wait_for_the_search() {
+        let table = Arc::new(Mutex::new(TranspositionTable::new(1)));
+        let agent = Agent::AlphaBeta(SearchLimits {
+            think_time: 60.0,
+            max_depth: 64,
+        });
+        let mut worker = Worker::spawn(&agent, &board(), &Turn::Player1, table.clone());
+
+        let start = Instant::now();
+        assert_eq!(worker.poll(), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Cancelling gives back the best move found so far
+        worker.cancel.store(true, Ordering::Relaxed);
+        let chosen = wait(&mut worker, Duration::from_secs(10));
+        assert!(matches!(chosen, Some(Action::Drop(_))));
+        assert!(table.lock().is_ok());
+    }
+
+    #[test]
+    fn dropping_the_worker_stops_the_search() {
+        let table = Arc::new(Mutex::new(TranspositionTable::new(1)));
+        let agent = Agent::AlphaBeta(SearchLimits {
+            think_time: 60.0,
+            max_depth: 64,
+        });
+        let worker = Worker::spawn(&agent, &board(), &Turn::Player1, table.clone());
+        let cancel = worker.cancel.clone();
+        drop(worker);
+        assert!(cancel.load(Ordering::Relaxed));
+
+        // The thread lets go of the table once it has stopped
+        let start = Instant::now();
+        while Arc::strong_count(&table) > 1 {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "the search kept going"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}